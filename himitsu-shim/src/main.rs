@@ -3,86 +3,495 @@ extern crate log;
 
 use std::io;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixStream;
 
-use himitsu::message::{HimitsuMessage, HimitsuResponse};
+use himitsu::config::{self, HimitsuConfiguration};
+use himitsu::git;
+use himitsu::message::{self, FileBlob, HimitsuMessage, HimitsuResponse, MessageLimits};
+use himitsu::output::Format;
+use himitsu::{FindingState, ScanResult, ScanResults};
 
-#[tokio::main]
-async fn main() {
-    debug!("Welcome To Himitsu Shim");
-    // Himitsu Bypass Block
-    let block_bypass = std::env::var("HBB").map(|x| x.len() > 0).unwrap_or(false);
+fn describe(secret: &ScanResult) -> String {
+    let commit = secret
+        .commit
+        .as_ref()
+        .map(|c| format!("commit {}: ", &c[..c.len().min(12)]))
+        .unwrap_or_default();
+    let location = secret
+        .location()
+        .map(|l| format!("{commit}{l}: "))
+        .unwrap_or(commit);
+
+    let mut description = format!(
+        "{location}[{}] {} was found by system {} with contents: {}",
+        secret.severity, secret.name, secret.system, secret.value
+    );
+
+    if let Some(remediation) = &secret.remediation {
+        description.push_str(&format!("\n    Remediation: {remediation}"));
+    }
 
-    let socket = match home::home_dir() {
-        Some(mut path) => {
-            path.push("Library/Group Containers/U52857STQU.PassportControl/himitsuSocket");
-            path.to_str().unwrap().to_string()
+    description
+}
+
+fn print_results(prefix: &str, secrets: &ScanResults) {
+    for secret in secrets {
+        match secret.state {
+            FindingState::Active => println!("{prefix}{}", describe(secret)),
+            FindingState::Removed => println!("REMOVED: {}", describe(secret)),
+            FindingState::Suppressed => println!("SUPPRESSED: {}", describe(secret)),
+            FindingState::Warning => println!("WARNING: {}", describe(secret)),
         }
-        None => {
-            println!("Failed to find home directory");
-            if block_bypass {
-                std::process::exit(0);
-            } else {
-                std::process::exit(2);
+    }
+}
+
+const USAGE: &str =
+    "Usage: himitsu-shim [--staged | --pre-push | --status] [--socket <path>] [--config <path>] \
+                     [--format json|sarif|junit]";
+
+/// How much file content is sent to the daemon in one request
+const BATCH_SIZE: usize = 4 * 1024 * 1024;
+/// Files larger than this can't be sent within the default message size limit
+const MAX_FILE_SIZE: usize = 12 * 1024 * 1024;
+
+#[derive(Default)]
+struct Options {
+    /// Without a format results are printed as text for people to read
+    format: Option<Format>,
+    socket: Option<String>,
+    /// Configuration used when the daemon is unreachable
+    config: Option<String>,
+    /// Scan staged files from the Git index instead of a diff on stdin
+    staged: bool,
+    /// Scan every commit being pushed, as listed by Git on stdin
+    pre_push: bool,
+    /// Print what the daemon is running with instead of scanning
+    status: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    println!("{message}\n{USAGE}");
+    std::process::exit(2);
+}
+
+/// Parse `--staged`, `--pre-push`, `--status`, `--format <format>`,
+/// `--socket <path>` and `--config <path>` from the command line, also
+/// accepting the `--flag=value` form
+fn parse_options() -> Options {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if arg == "--staged" || arg == "--pre-push" {
+            options.staged |= arg == "--staged";
+            options.pre_push |= arg == "--pre-push";
+            if options.staged && options.pre_push {
+                usage_error("--staged and --pre-push can't be used together");
             }
+            continue;
+        }
+        if arg == "--status" {
+            options.status = true;
+            continue;
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        if !["--format", "--socket", "--config"].contains(&flag.as_str()) {
+            usage_error(&format!("Unknown option {flag}"));
+        }
+        let value = match value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => usage_error(&format!("Missing value for {flag}")),
+        };
+
+        match flag.as_str() {
+            "--format" => match value.parse::<Format>() {
+                Ok(format) => options.format = Some(format),
+                Err(e) => usage_error(&e),
+            },
+            "--socket" => options.socket = Some(value),
+            _ => options.config = Some(value),
+        }
+    }
+    options
+}
+
+/// Render the results of a response in a structured format and return
+/// the exit code
+fn print_structured(format: Format, response: HimitsuResponse) -> i32 {
+    let (results, return_code) = match response {
+        HimitsuResponse::Clean => (ScanResults::new(), 0),
+        HimitsuResponse::SecretsFound(secrets) => (secrets, 1),
+        HimitsuResponse::SecretsFoundSilent(secrets)
+        | HimitsuResponse::Warnings(secrets)
+        | HimitsuResponse::Informational(secrets) => (secrets, 0),
+        HimitsuResponse::Error(e) => {
+            eprintln!("Error: {}", e);
+            return 2;
+        }
+        HimitsuResponse::Hello { .. } | HimitsuResponse::Status(_) => {
+            eprintln!("Error: Unexpected response from Himitsu");
+            return 2;
         }
     };
 
-    let diff = io::read_to_string(io::stdin()).unwrap();
+    println!("{}", format.render(&results));
+    return_code
+}
 
+/// What the shim was asked to scan
+enum Scan {
+    /// A diff read from stdin
+    Diff(String),
+    /// The staged contents of changed files read from the Git index
+    Files(Vec<FileBlob>),
+    /// The patches of the commits being pushed
+    Commits(Vec<String>),
+}
+
+/// Record which commit the findings in a response were introduced in
+fn tag_commit(response: HimitsuResponse, commit: &str) -> HimitsuResponse {
+    response.map_results(|mut result| {
+        result.commit = Some(commit.to_string());
+        result
+    })
+}
+
+fn commit_patch(commit: &str) -> Result<String, HimitsuResponse> {
+    git::commit_patch(commit)
+        .map_err(|e| HimitsuResponse::Error(format!("Could not read commit {commit}: {e}")))
+}
+
+/// Introduce the shim to the daemon and return the capabilities it shares
+/// with us. Returns `None` if the daemon predates the handshake, in which
+/// case it will have closed the connection, and an error if it doesn't
+/// support our protocol version.
+async fn hello(stream: &mut UnixStream) -> Result<Option<Vec<String>>, String> {
+    let hello = HimitsuMessage::Hello {
+        protocol_version: message::PROTOCOL_VERSION,
+        client_name: format!("himitsu-shim {}", env!("CARGO_PKG_VERSION")),
+        capabilities: message::CAPABILITIES
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+    };
+    message::serialize_and_send_response(hello, stream)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match message::parse_incoming_message(stream, &MessageLimits::default()).await {
+        Ok(HimitsuResponse::Hello {
+            protocol_version,
+            server_name,
+            capabilities,
+        }) => {
+            debug!("Connected to {server_name} speaking protocol version {protocol_version}");
+            Ok(Some(capabilities))
+        }
+        Ok(HimitsuResponse::Error(e)) => Err(e),
+        Ok(response) => Err(format!("Unexpected response to hello: {response:?}")),
+        Err(_) => Ok(None),
+    }
+}
+
+async fn request(stream: &mut UnixStream, message: HimitsuMessage) -> HimitsuResponse {
+    if let Err(e) = message::serialize_and_send_response(message, stream).await {
+        return HimitsuResponse::Error(e.to_string());
+    }
+
+    message::parse_incoming_message(stream, &MessageLimits::default())
+        .await
+        .unwrap_or_else(|e| HimitsuResponse::Error(e.to_string()))
+}
+
+/// Ask the daemon what it's running with, such as how stale its
+/// configuration is, and return the exit code
+async fn print_status(socket: Option<String>) -> i32 {
+    let socket = match himitsu::socket::resolve(socket) {
+        Some(socket) => socket,
+        None => {
+            println!("Failed to find the Himitsu socket, set it with --socket or HIMITSU_SOCKET");
+            return 2;
+        }
+    };
     let mut stream = match UnixStream::connect(&socket).await {
         Ok(stream) => stream,
         Err(e) => {
-            println!("Failed to connect to Himitsu: {}. Socket Path: {socket}", e);
-            if block_bypass {
-                std::process::exit(0);
-            } else {
-                std::process::exit(2);
+            println!("Failed to connect to Himitsu: {e}. Socket Path: {socket}");
+            return 2;
+        }
+    };
+
+    match hello(&mut stream).await {
+        Ok(Some(capabilities)) if capabilities.iter().any(|c| c == message::CAPABILITY_STATUS) => {}
+        Ok(_) => {
+            println!("This version of Himitsu can't report its status");
+            return 2;
+        }
+        Err(e) => {
+            println!("Himitsu refused the connection: {e}");
+            return 2;
+        }
+    }
+
+    match request(&mut stream, HimitsuMessage::Status).await {
+        HimitsuResponse::Status(status) => {
+            println!("{status}");
+            0
+        }
+        HimitsuResponse::Error(e) => {
+            println!("Error: {e}");
+            2
+        }
+        response => {
+            println!("Error: Unexpected response from Himitsu: {response:?}");
+            2
+        }
+    }
+}
+
+/// Split files into requests that stay well below the daemon's message size
+/// limit, returning the files that are too large to ever be sent separately
+fn batch_files(files: Vec<FileBlob>) -> (Vec<Vec<FileBlob>>, Vec<git::SkippedFile>) {
+    let mut batches: Vec<Vec<FileBlob>> = Vec::new();
+    let mut too_large = Vec::new();
+    let mut batch_size = 0;
+    for file in files {
+        if file.content.len() > MAX_FILE_SIZE {
+            too_large.push(git::SkippedFile {
+                path: file.path,
+                reason: "too large to send to Himitsu",
+            });
+            continue;
+        }
+
+        if batches.is_empty() || batch_size + file.content.len() > BATCH_SIZE {
+            batches.push(Vec::new());
+            batch_size = 0;
+        }
+        batch_size += file.content.len();
+        batches.last_mut().unwrap().push(file);
+    }
+    (batches, too_large)
+}
+
+async fn scan_with_daemon(
+    mut stream: UnixStream,
+    scan: Scan,
+    capabilities: &[String],
+) -> HimitsuResponse {
+    let files = match scan {
+        Scan::Diff(diff) => {
+            return request(&mut stream, HimitsuMessage::ScanCodeDiff { diff }).await
+        }
+        Scan::Files(files) => files,
+        Scan::Commits(commits) => {
+            let mut response = HimitsuResponse::Clean;
+            for commit in commits {
+                let scanned = match commit_patch(&commit) {
+                    Ok(diff) => request(&mut stream, HimitsuMessage::ScanCodeDiff { diff }).await,
+                    Err(e) => e,
+                };
+                response = response.merge(tag_commit(scanned, &commit));
             }
+            return response;
         }
     };
 
-    // Create a message to send to the local Himitsu handler
-    let scan_message = HimitsuMessage::ScanCodeDiff { diff };
-    let scan_message = serde_json::to_string(&scan_message).unwrap();
+    // Daemons that can't scan files still get to see the staged changes
+    if !capabilities
+        .iter()
+        .any(|c| c == message::CAPABILITY_SCAN_FILES)
+    {
+        return match staged_diff() {
+            Ok(diff) => request(&mut stream, HimitsuMessage::ScanCodeDiff { diff }).await,
+            Err(e) => HimitsuResponse::Error(e),
+        };
+    }
+
+    let (batches, too_large) = batch_files(files);
+    let mut response = HimitsuResponse::Clean;
+    for files in batches {
+        let batch = request(&mut stream, HimitsuMessage::ScanFiles { files }).await;
+        response = response.merge(batch);
+    }
+
+    // Files that weren't scanned can't be let through unnoticed, but secrets
+    // found in the rest are still worth showing
+    if too_large.is_empty() {
+        return response;
+    }
+    for file in &too_large {
+        eprintln!("Skipping {} which is {}", file.path, file.reason);
+    }
+    match response {
+        HimitsuResponse::SecretsFound(_) | HimitsuResponse::Error(_) => response,
+        _ => HimitsuResponse::Error(format!(
+            "{} staged file(s) could not be scanned, set HBB to commit them anyway",
+            too_large.len()
+        )),
+    }
+}
+
+/// Every new commit in the refs Git says are being pushed, without
+/// duplicates when several refs share commits
+fn pushed_commits() -> io::Result<Vec<String>> {
+    let input = io::read_to_string(io::stdin())?;
+    let mut commits: Vec<String> = Vec::new();
+    for pushed in git::parse_pushed_refs(&input) {
+        debug!(
+            "Checking push of {} to {}",
+            pushed.local_ref, pushed.remote_ref
+        );
+        for commit in git::pushed_commits(&pushed)? {
+            if !commits.contains(&commit) {
+                commits.push(commit);
+            }
+        }
+    }
+    Ok(commits)
+}
 
-    stream.write_u32(scan_message.len() as u32).await.unwrap();
-    stream.write_all(scan_message.as_bytes()).await.unwrap();
+fn staged_diff() -> Result<String, String> {
+    git::run(&["diff", "--cached"])
+        .map(|diff| String::from_utf8_lossy(&diff).to_string())
+        .map_err(|e| format!("Could not read staged changes: {e}"))
+}
 
-    let response_length = stream.read_u32().await.unwrap();
-    let mut buf = vec![0; response_length as usize];
-    stream.read_exact(&mut buf).await.unwrap();
+/// Scan in process when the daemon can't be reached so a crashed daemon
+/// neither blocks every commit nor turns protection off. Uses the local
+/// configuration if there is one and the built in rules otherwise.
+fn scan_locally(config: Option<String>, scan: Scan) -> HimitsuResponse {
+    eprintln!("Scanning without the Himitsu daemon in degraded mode");
 
-    let response: HimitsuResponse = serde_json::from_slice(&buf).unwrap();
+    let config = match config::resolve_path(config) {
+        Some(path) => match HimitsuConfiguration::new_from_file(path.clone()) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Could not load configuration from {path}: {e}. Using the default rules");
+                HimitsuConfiguration::default()
+            }
+        },
+        None => HimitsuConfiguration::default(),
+    };
 
-    let return_code = match response {
-        HimitsuResponse::Clean => {
+    let response = match scan {
+        Scan::Diff(diff) => config.check(diff),
+        Scan::Files(files) => config.check_files(files),
+        Scan::Commits(commits) => {
+            commits
+                .iter()
+                .fold(HimitsuResponse::Clean, |response, commit| {
+                    let scanned = match commit_patch(commit) {
+                        Ok(diff) => config.check(diff),
+                        Err(e) => e,
+                    };
+                    response.merge(tag_commit(scanned, commit))
+                })
+        }
+    };
+    config.redact(response)
+}
+
+#[tokio::main]
+async fn main() {
+    debug!("Welcome To Himitsu Shim");
+    // Himitsu Bypass Block
+    let block_bypass = std::env::var("HBB").map(|x| x.len() > 0).unwrap_or(false);
+    let options = parse_options();
+
+    if options.status {
+        std::process::exit(print_status(options.socket).await);
+    }
+
+    let scan = if options.staged {
+        match git::staged_files() {
+            Ok((files, skipped)) => {
+                for file in skipped {
+                    eprintln!("Skipping {} which is {}", file.path, file.reason);
+                }
+                Scan::Files(files)
+            }
+            Err(e) => {
+                println!("Failed to read staged files: {e}");
+                std::process::exit(if block_bypass { 0 } else { 2 });
+            }
+        }
+    } else if options.pre_push {
+        match pushed_commits() {
+            Ok(commits) => Scan::Commits(commits),
+            Err(e) => {
+                println!("Failed to find the commits being pushed: {e}");
+                std::process::exit(if block_bypass { 0 } else { 2 });
+            }
+        }
+    } else {
+        Scan::Diff(io::read_to_string(io::stdin()).unwrap())
+    };
+
+    let response = match himitsu::socket::resolve(options.socket) {
+        Some(socket) => match UnixStream::connect(&socket).await {
+            Ok(mut stream) => match hello(&mut stream).await {
+                Ok(Some(capabilities)) => scan_with_daemon(stream, scan, &capabilities).await,
+                // Older daemons close the connection when they get a message
+                // they don't know so reconnect and skip the handshake
+                Ok(None) => match UnixStream::connect(&socket).await {
+                    Ok(stream) => scan_with_daemon(stream, scan, &[]).await,
+                    Err(e) => {
+                        eprintln!("Failed to reconnect to Himitsu: {e}. Socket Path: {socket}");
+                        scan_locally(options.config, scan)
+                    }
+                },
+                Err(e) => {
+                    eprintln!("Himitsu refused the connection: {e}");
+                    scan_locally(options.config, scan)
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to connect to Himitsu: {e}. Socket Path: {socket}");
+                scan_locally(options.config, scan)
+            }
+        },
+        None => {
+            eprintln!("Failed to find the Himitsu socket, set it with --socket or HIMITSU_SOCKET");
+            scan_locally(options.config, scan)
+        }
+    };
+
+    let return_code = match (options.format, response) {
+        (Some(format), response) => print_structured(format, response),
+        (None, HimitsuResponse::Clean) => {
             println!("Himitsu Found No Secrets");
             0
         }
-        HimitsuResponse::SecretsFound(secrets) => {
-            for secret in secrets {
-                println!(
-                    "{} was found by system {} with contents: {}",
-                    secret.name, secret.system, secret.value
-                );
-            }
+        (None, HimitsuResponse::SecretsFound(secrets)) => {
+            print_results("", &secrets);
             1
         }
-        HimitsuResponse::SecretsFoundSilent(secrets) => {
-            for secret in secrets {
-                println!(
-                    "IGNORING THAT: {} was found by system {} with contents: {}",
-                    secret.name, secret.system, secret.value
-                );
-            }
+        (None, HimitsuResponse::SecretsFoundSilent(secrets)) => {
+            print_results("IGNORING THAT: ", &secrets);
             0
         }
-        HimitsuResponse::Error(e) => {
+        (None, HimitsuResponse::Warnings(secrets)) => {
+            println!("Himitsu Found Secrets Below The Blocking Threshold");
+            print_results("", &secrets);
+            0
+        }
+        (None, HimitsuResponse::Informational(secrets)) => {
+            println!("Himitsu Found No New Secrets");
+            print_results("", &secrets);
+            0
+        }
+        (None, HimitsuResponse::Error(e)) => {
             println!("Error: {}", e);
             2
         }
+        (None, HimitsuResponse::Hello { .. } | HimitsuResponse::Status(_)) => {
+            println!("Error: Unexpected response from Himitsu");
+            2
+        }
     };
 
     // If the user has asked to bypass, we will return success no matter what
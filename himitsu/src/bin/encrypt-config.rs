@@ -1,7 +1,8 @@
-use std::{env, io::Read};
+use std::{env, io::Read, time::{SystemTime, UNIX_EPOCH}};
 
 use base64::{prelude::BASE64_STANDARD, Engine};
-use ring::{aead::{Aad, BoundKey, Nonce, NonceSequence, SealingKey, UnboundKey, AES_256_GCM, NONCE_LEN}, error::Unspecified, rand::{SecureRandom, SystemRandom}};
+use himitsu::envelope::{Envelope, Metadata};
+use ring::{aead::{Aad, BoundKey, Nonce, NonceSequence, SealingKey, UnboundKey, AES_256_GCM, NONCE_LEN}, error::Unspecified, rand::{SecureRandom, SystemRandom}, signature::{Ed25519KeyPair, KeyPair}};
 
 
 struct SingleNonceSequence(Option<Vec<u8>>);
@@ -25,18 +26,74 @@ fn new_single_nonce_sequence(rand: &SystemRandom) -> (Vec<u8>, SingleNonceSequen
     (nonce_bytes.clone(), SingleNonceSequence(Some(nonce_bytes)))
 }
 
+// Signing keys are the hex encoded 32 byte Ed25519 seed
+fn signing_key_pair(seed: &str) -> Ed25519KeyPair {
+    let seed = hex::decode(seed).unwrap();
+    Ed25519KeyPair::from_seed_unchecked(&seed).unwrap()
+}
+
+fn generate_signing_key(rand: &SystemRandom) {
+    let mut seed = vec![0; 32];
+    rand.fill(&mut seed).unwrap();
+    let key_pair = signing_key_pair(&hex::encode(&seed));
+
+    println!("Signing Key (HIMITSU_SIGNING_KEY)");
+    println!("---------------------------------");
+    println!("{}", hex::encode(&seed));
+    println!("Verification Key");
+    println!("----------------");
+    println!("{}", hex::encode(key_pair.public_key().as_ref()));
+}
+
+// Wrap the configuration with an Ed25519 signature so clients can tell it
+// came from us. The metadata is signed too as anyone with the shared key
+// could seal any metadata.
+fn sign_configuration(config: Vec<u8>, metadata: &Metadata, seed: &str) -> Vec<u8> {
+    let key_pair = signing_key_pair(seed);
+    let message = metadata.signed_message(&config).unwrap();
+    let signed = serde_json::json!({
+        "configuration": BASE64_STANDARD.encode(&config),
+        "metadata": metadata,
+        "signature": BASE64_STANDARD.encode(key_pair.sign(&message)),
+    });
+    signed.to_string().into_bytes()
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let config_path = args.get(1).map(|x| x.to_owned()).unwrap();
+
+    // Create a new instance of SystemRandom to be used as the single source of entropy
+    let rand = SystemRandom::new();
+
+    if args.get(1).map(|x| x.as_str()) == Some("--generate-signing-key") {
+        generate_signing_key(&rand);
+        return;
+    }
+
+    // --legacy writes the old unversioned nonce || ciphertext format for
+    // clients that don't understand envelopes yet
+    let legacy = args.iter().any(|x| x == "--legacy");
+    let config_path = args.iter().skip(1).find(|x| !x.starts_with("--")).map(|x| x.to_owned()).unwrap();
 
     let file = std::fs::File::open(config_path).unwrap();
     let mut in_out: Vec<u8> = vec![];
     std::io::BufReader::new(file).read_to_end(&mut in_out).unwrap();
 
-    // Create a new instance of SystemRandom to be used as the single source of entropy
-    let rand = SystemRandom::new();
-
-    let (mut nonce, nonce_sequence) = new_single_nonce_sequence(&rand);
+    // The name and version let clients refuse another environment's config
+    // or an older one than they have seen
+    let issued_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let metadata = Metadata {
+        name: env::var("HIMITSU_CONFIG_NAME").unwrap_or_else(|_| "default".to_string()),
+        // Defaulting to the issue time keeps versions increasing without
+        // having to track them
+        version: env::var("HIMITSU_CONFIG_VERSION").map(|v| v.parse().unwrap()).unwrap_or(issued_at),
+        issued_at,
+    };
+
+    // Sign before encrypting if we were given a signing key
+    if let Ok(seed) = env::var("HIMITSU_SIGNING_KEY") {
+        in_out = sign_configuration(in_out, &metadata, &seed);
+    }
 
     let key = env::var("HIMITSU_KEY").unwrap();
     let key_bytes = hex::decode(key).unwrap();
@@ -45,6 +102,19 @@ fn main() {
         panic!("Key must be {} bytes long", AES_256_GCM.key_len());
     }
 
+    if !legacy {
+        // The key id tells clients holding several keys which one to use
+        let key_id = env::var("HIMITSU_KEY_ID").unwrap_or_else(|_| "default".to_string());
+
+        let sealed = Envelope::seal(&key_id, &key_bytes, &metadata, &in_out).unwrap();
+        println!("Encrypted Configuration (key id {}, {} version {})", key_id, metadata.name, metadata.version);
+        println!("-----------------------");
+        println!("{}", BASE64_STANDARD.encode(&sealed));
+        return;
+    }
+
+    let (mut nonce, nonce_sequence) = new_single_nonce_sequence(&rand);
+
     // Create a new AEAD key without a designated role or nonce sequence
     let unbound_key = UnboundKey::new(&AES_256_GCM, &key_bytes).unwrap();
 
@@ -0,0 +1,33 @@
+use std::env;
+
+use himitsu::config::HimitsuConfiguration;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let config_path = match args.get(1) {
+        Some(path) => path.to_owned(),
+        None => {
+            eprintln!("Usage: lint-config <path to configuration>");
+            std::process::exit(2);
+        }
+    };
+
+    let config = match HimitsuConfiguration::new_from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Configuration could not be loaded: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let problems = config.lint();
+    if problems.is_empty() {
+        println!("Configuration is valid");
+        return;
+    }
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+    std::process::exit(1);
+}
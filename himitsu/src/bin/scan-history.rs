@@ -0,0 +1,302 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::Path,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use himitsu::config::{self, HimitsuConfiguration};
+use himitsu::git::{self, CommitInfo};
+use himitsu::output::Format;
+use himitsu::storage;
+use himitsu::{FindingState, ScanResult, ScanResults};
+
+const USAGE: &str = "Usage: scan-history [--config <path>] [--since <date>] \
+                     [--checkpoint <path>] [--format json|sarif|junit] [<ref or range>...]";
+
+/// How many commits are scanned between checkpoint saves
+const CHECKPOINT_INTERVAL: usize = 100;
+
+#[derive(Default)]
+struct Options {
+    config: Option<String>,
+    since: Option<String>,
+    checkpoint: Option<String>,
+    format: Option<Format>,
+    revisions: Vec<String>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    std::process::exit(2);
+}
+
+fn parse_options() -> Options {
+    let mut args = env::args().skip(1);
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.revisions.push(arg);
+            continue;
+        }
+
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        if !["--config", "--since", "--checkpoint", "--format"].contains(&flag.as_str()) {
+            usage_error(&format!("Unknown option {flag}"));
+        }
+        let value = match value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => usage_error(&format!("Missing value for {flag}")),
+        };
+
+        match flag.as_str() {
+            "--config" => options.config = Some(value),
+            "--since" => options.since = Some(value),
+            "--checkpoint" => options.checkpoint = Some(value),
+            _ => match value.parse::<Format>() {
+                Ok(format) => options.format = Some(format),
+                Err(e) => usage_error(&e),
+            },
+        }
+    }
+    options
+}
+
+/// The first time a secret appeared in the history
+#[derive(Serialize, Deserialize)]
+struct Introduced {
+    result: ScanResult,
+    author: String,
+    date: String,
+    #[serde(default)]
+    timestamp: i64,
+}
+
+/// Progress through the history saved so an interrupted scan of a large
+/// repository can pick up where it left off
+#[derive(Serialize, Deserialize, Default)]
+struct Checkpoint {
+    /// Every commit scanned so far. Commits fetched later can have older
+    /// dates, so no position in the history marks where the scan got to.
+    #[serde(default)]
+    scanned: BTreeSet<String>,
+    /// Findings keyed by value hash so each secret is only reported once
+    findings: BTreeMap<String, Introduced>,
+}
+
+impl Checkpoint {
+    fn load(path: &str) -> Checkpoint {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                eprintln!("Could not read checkpoint {path}: {e}");
+                std::process::exit(2);
+            }),
+            Err(_) => Checkpoint::default(),
+        }
+    }
+
+    fn save(&self, path: &str) {
+        let saved = storage::write_atomically(Path::new(path), &serde_json::to_vec(self).unwrap());
+        if let Err(e) = saved {
+            eprintln!("Could not save checkpoint {path}: {e}");
+        }
+    }
+
+    fn record(&mut self, commit: &CommitInfo, results: ScanResults) {
+        for mut result in results {
+            if result.state == FindingState::Removed {
+                continue;
+            }
+
+            result.commit = Some(commit.sha.clone());
+            self.findings
+                .entry(result.value_hash.clone())
+                .or_insert_with(|| Introduced {
+                    result,
+                    author: commit.author.clone(),
+                    date: commit.date.clone(),
+                    timestamp: commit.timestamp,
+                });
+        }
+        self.scanned.insert(commit.sha.clone());
+    }
+
+    /// The commits that haven't been scanned yet, oldest first
+    fn remaining<'a>(&self, commits: &'a [CommitInfo]) -> Vec<&'a CommitInfo> {
+        commits
+            .iter()
+            .filter(|commit| !self.scanned.contains(&commit.sha))
+            .collect()
+    }
+}
+
+fn load_configuration(path: Option<String>) -> HimitsuConfiguration {
+    match config::resolve_path(path) {
+        Some(path) => match HimitsuConfiguration::new_from_file(path.clone()) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Could not load configuration from {path}: {e}. Using the default rules");
+                HimitsuConfiguration::default()
+            }
+        },
+        None => HimitsuConfiguration::default(),
+    }
+}
+
+fn main() {
+    let options = parse_options();
+    let config = load_configuration(options.config);
+
+    let commits = match git::history(&options.revisions, options.since.as_deref()) {
+        Ok(commits) => commits,
+        Err(e) => {
+            eprintln!("Could not list commits: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let mut checkpoint = match &options.checkpoint {
+        Some(path) => Checkpoint::load(path),
+        None => Checkpoint::default(),
+    };
+
+    let remaining = checkpoint.remaining(&commits);
+    eprintln!("Scanning {} of {} commits", remaining.len(), commits.len());
+
+    for (i, commit) in remaining.iter().enumerate() {
+        match git::commit_patch(&commit.sha) {
+            // Findings are written to the checkpoint and printed, so they
+            // are redacted straight away
+            Ok(patch) => checkpoint.record(commit, config.redact_results(config.scan(patch))),
+            Err(e) => {
+                eprintln!("Could not read commit {}: {e}", commit.sha);
+                // Keep what was scanned so far so the scan can resume here
+                if let Some(path) = &options.checkpoint {
+                    checkpoint.save(path);
+                }
+                std::process::exit(2);
+            }
+        }
+
+        if let Some(path) = &options.checkpoint {
+            if (i + 1) % CHECKPOINT_INTERVAL == 0 {
+                eprintln!("Scanned {} commits", i + 1);
+                checkpoint.save(path);
+            }
+        }
+    }
+
+    if let Some(path) = &options.checkpoint {
+        checkpoint.save(path);
+    }
+
+    let found = !checkpoint.findings.is_empty();
+    match options.format {
+        Some(format) => {
+            let results: ScanResults = checkpoint
+                .findings
+                .into_values()
+                .map(|introduced| introduced.result)
+                .collect();
+            println!("{}", format.render(&results));
+        }
+        None => {
+            let mut findings: Vec<Introduced> = checkpoint.findings.into_values().collect();
+            findings.sort_by_key(|introduced| introduced.timestamp);
+            for introduced in findings {
+                let result = &introduced.result;
+                println!(
+                    "{} {} {}: {}[{}] {} was found by system {} with contents: {}",
+                    result.commit.as_deref().unwrap_or_default(),
+                    introduced.date,
+                    introduced.author,
+                    result
+                        .location()
+                        .map(|l| format!("{l}: "))
+                        .unwrap_or_default(),
+                    result.severity,
+                    result.name,
+                    result.system,
+                    result.value
+                );
+            }
+            if !found {
+                println!("Himitsu Found No Secrets");
+            }
+        }
+    }
+
+    std::process::exit(if found { 1 } else { 0 });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(sha: &str) -> CommitInfo {
+        CommitInfo {
+            sha: sha.to_string(),
+            author: "Jane <jane@example.com>".to_string(),
+            date: "2024-01-02T03:04:05+00:00".to_string(),
+            timestamp: 1704164645,
+        }
+    }
+
+    fn finding(value: &str) -> ScanResult {
+        serde_json::from_str(&format!(
+            r#"{{"system": "Regex", "name": "AwsKey", "value": "{value}", "value_hash": "{value}"}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_checkpoint_dedup() {
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.record(&commit("a"), ScanResults::from([finding("key")]));
+        checkpoint.record(
+            &commit("b"),
+            ScanResults::from([finding("key"), finding("other")]),
+        );
+
+        assert_eq!(checkpoint.findings.len(), 2);
+        // Secrets are attributed to the commit that introduced them
+        assert_eq!(
+            checkpoint.findings["key"].result.commit.as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            checkpoint.findings["other"].result.commit.as_deref(),
+            Some("b")
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let commits = vec![commit("a"), commit("b"), commit("c")];
+        let mut checkpoint = Checkpoint::default();
+        assert_eq!(checkpoint.remaining(&commits).len(), 3);
+
+        checkpoint.record(&commits[0], ScanResults::new());
+        checkpoint.record(&commits[1], ScanResults::new());
+        let checkpoint: Checkpoint =
+            serde_json::from_slice(&serde_json::to_vec(&checkpoint).unwrap()).unwrap();
+        let remaining = |commits: &[CommitInfo]| -> Vec<String> {
+            checkpoint
+                .remaining(commits)
+                .iter()
+                .map(|c| c.sha.clone())
+                .collect()
+        };
+        assert_eq!(remaining(&commits), vec!["c"]);
+
+        // A commit fetched since with an older date than those scanned is
+        // still picked up
+        let fetched = vec![commit("d"), commit("a"), commit("b"), commit("c")];
+        assert_eq!(remaining(&fetched), vec!["d", "c"]);
+        assert_eq!(remaining(&[commit("e"), commit("f")]), vec!["e", "f"]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use ring::digest::{digest, SHA256};
+
+use crate::{
+    error::{HResult, HimitsuError},
+    storage,
+};
+
+/// Environment variable overriding how old a cached configuration may be,
+/// as a duration such as `3days`
+pub const MAX_STALENESS_ENV: &str = "HIMITSU_CONFIG_MAX_STALENESS";
+
+const DEFAULT_MAX_STALENESS: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Where fetched configurations are cached, `$XDG_CACHE_HOME/himitsu`
+/// falling back to `~/.cache/himitsu`
+pub fn default_dir() -> Option<PathBuf> {
+    storage::xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `HIMITSU_CONFIG_MAX_STALENESS` if it is set to a valid duration,
+/// otherwise a week
+pub fn default_max_staleness() -> Duration {
+    match env::var(MAX_STALENESS_ENV) {
+        Ok(value) => humantime::parse_duration(&value).unwrap_or_else(|e| {
+            warn!("Ignoring {MAX_STALENESS_ENV}={value}: {e}");
+            DEFAULT_MAX_STALENESS
+        }),
+        Err(_) => DEFAULT_MAX_STALENESS,
+    }
+}
+
+/// The last configuration fetched from a URL that was successfully
+/// decrypted and verified, kept exactly as it was served. It is decrypted
+/// and verified again when it is used.
+pub struct ConfigCache {
+    path: PathBuf,
+}
+
+impl ConfigCache {
+    pub fn new(dir: PathBuf, url: &str) -> Self {
+        // Named after the URL so several configurations can be cached
+        // without the URL, which may hold a token, being written to disk
+        let name = hex::encode(&digest(&SHA256, url.as_bytes()).as_ref()[..16]);
+        Self {
+            path: dir.join(format!("config-{name}")),
+        }
+    }
+
+    pub fn store(&self, config: &str) -> HResult<()> {
+        Ok(storage::write_atomically(&self.path, config.as_bytes())?)
+    }
+
+    /// Mark the cached configuration as fetched just now, for when the server
+    /// says it hasn't changed so its age keeps reflecting the last fetch
+    pub fn touch(&self) -> HResult<()> {
+        fs::File::options()
+            .write(true)
+            .open(&self.path)?
+            .set_modified(SystemTime::now())?;
+        Ok(())
+    }
+
+    /// The cached configuration and when it was fetched, as long as it isn't
+    /// older than `max_staleness`
+    pub fn load(&self, max_staleness: Duration) -> HResult<(String, SystemTime)> {
+        let fetched_at = fs::metadata(&self.path)?.modified()?;
+        let age = fetched_at.elapsed().unwrap_or_default();
+        if age > max_staleness {
+            return Err(HimitsuError::CacheError(format!(
+                "Cached configuration is {} old, more than the maximum of {}",
+                humantime::format_duration(Duration::from_secs(age.as_secs())),
+                humantime::format_duration(max_staleness)
+            )));
+        }
+
+        Ok((fs::read_to_string(&self.path)?, fetched_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_cache() {
+        let dir = env::temp_dir().join(format!("himitsu-cache-{}", std::process::id()));
+        let cache = ConfigCache::new(dir.clone(), "https://example.com/config?token=1");
+        assert!(cache.load(DEFAULT_MAX_STALENESS).is_err());
+
+        cache.store("config").unwrap();
+        let (config, fetched_at) = cache.load(DEFAULT_MAX_STALENESS).unwrap();
+        assert_eq!(config, "config");
+        assert!(fetched_at.elapsed().unwrap() < DEFAULT_MAX_STALENESS);
+
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(matches!(
+            cache.load(Duration::ZERO),
+            Err(HimitsuError::CacheError(_))
+        ));
+
+        std::thread::sleep(Duration::from_millis(10));
+        cache.touch().unwrap();
+        let (_, touched_at) = cache.load(DEFAULT_MAX_STALENESS).unwrap();
+        assert!(touched_at > fetched_at);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,31 +1,201 @@
 use base64::{prelude::BASE64_STANDARD, Engine};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
 use ring::{
     aead::{Aad, BoundKey, Nonce, NonceSequence, OpeningKey, UnboundKey, AES_256_GCM, NONCE_LEN},
     error::Unspecified,
+    signature::{UnparsedPublicKey, ED25519},
 };
 use secrecy::{ExposeSecret, Secret};
 use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
+    cache::{self, ConfigCache},
+    envelope::{Envelope, Metadata},
     error::{HResult, HimitsuError},
+    message::{FileBlob, HimitsuResponse, MessageLimits, Status},
+    policy::Policy,
+    redaction::Redaction,
+    rollback::{self, AcceptedVersions},
     scanners::Scanner,
+    storage, ScanResults,
 };
 
-enum ConfigurationSource {
+/// Environment variable pointing clients at a local configuration
+pub const CONFIG_ENV: &str = "HIMITSU_CONFIG";
+
+/// Resolve the local configuration file from an explicitly provided path,
+/// `HIMITSU_CONFIG` or `$XDG_CONFIG_HOME/himitsu/config.json`, falling back
+/// to `~/.config` when `XDG_CONFIG_HOME` is not set.
+pub fn resolve_path(path: Option<String>) -> Option<String> {
+    path.filter(|p| !p.is_empty())
+        .or_else(|| env::var(CONFIG_ENV).ok().filter(|p| !p.is_empty()))
+        .or_else(|| {
+            let path = storage::xdg_dir("XDG_CONFIG_HOME", ".config")?.join("config.json");
+            Some(path.to_string_lossy().to_string())
+        })
+}
+
+#[derive(Clone)]
+pub(crate) enum ConfigurationSource {
     Path(String),
-    Url(String, Option<Secret<String>>),
+    Url(String, Box<ConfigurationOptions>, Validators),
+}
+
+/// What the server told us identifies the version of a configuration it
+/// sent, so it can later tell us when it hasn't changed
+#[derive(Clone, Default)]
+pub(crate) struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// A configuration as it was served
+struct Fetched {
+    body: String,
+    validators: Validators,
+}
+
+/// How configurations fetched from a URL or string are protected
+#[derive(Clone)]
+pub struct ConfigurationOptions {
+    /// Hex encoded AES-256-GCM key the configuration is encrypted with. Used
+    /// for the legacy format and envelopes whose key id isn't in `keys`.
+    pub key: Option<Secret<String>>,
+    /// Hex encoded AES-256-GCM keys by key id, so clients can hold both the
+    /// old and new key while a key is rotated
+    pub keys: HashMap<String, Secret<String>>,
+    /// Hex encoded Ed25519 public key configurations must be signed with.
+    /// When set, unsigned configurations are rejected.
+    pub verification_key: Option<String>,
+    /// The configuration we expect to be given. When set, configurations
+    /// must be signed along with metadata carrying this name.
+    pub name: Option<String>,
+    /// Where the last accepted version of each configuration is kept to
+    /// refuse rolling back to older ones. Only signed versions are recorded.
+    pub state_path: Option<PathBuf>,
+    /// Where configurations fetched from a URL are cached for when a later
+    /// fetch fails
+    pub cache_dir: Option<PathBuf>,
+    /// How old a cached configuration may be and still be used
+    pub max_staleness: Duration,
+}
+
+impl ConfigurationOptions {
+    pub fn new(key: Option<Secret<String>>, verification_key: Option<String>) -> Self {
+        Self {
+            key,
+            keys: HashMap::new(),
+            verification_key,
+            name: None,
+            state_path: rollback::default_path(),
+            cache_dir: cache::default_dir(),
+            max_staleness: cache::default_max_staleness(),
+        }
+    }
+
+    /// Build options from a key specification which is either a single hex
+    /// encoded key or comma separated `id:key` pairs. An entry without an id
+    /// is the key for legacy configurations and ids that aren't listed. Every
+    /// key must be an AES-256 key.
+    pub fn from_key_spec(spec: String, verification_key: Option<String>) -> HResult<Self> {
+        let mut options = Self::new(None, verification_key);
+        for (i, entry) in spec.split(',').enumerate() {
+            // The entry itself isn't included as it holds a key
+            let malformed = |problem: &str| {
+                HimitsuError::CryptographyError(format!("Key entry {} {problem}", i + 1))
+            };
+            let (id, key) = match entry.split_once(':') {
+                Some((id, key)) => (Some(id.trim()), key.trim()),
+                None => (None, entry.trim()),
+            };
+            match hex::decode(key) {
+                Ok(bytes) if bytes.len() == AES_256_GCM.key_len() => {}
+                Ok(bytes) => {
+                    return Err(malformed(&format!(
+                        "is {} bytes long instead of {}",
+                        bytes.len(),
+                        AES_256_GCM.key_len()
+                    )))
+                }
+                Err(_) => return Err(malformed("is not a hex encoded key")),
+            }
+
+            let key = Secret::new(key.to_string());
+            let duplicate = match id {
+                Some("") => return Err(malformed("has an empty id")),
+                Some(id) => options.keys.insert(id.to_string(), key).is_some(),
+                None => options.key.replace(key).is_some(),
+            };
+            if duplicate {
+                return Err(malformed("repeats a key id"));
+            }
+        }
+        Ok(options)
+    }
+
+    fn is_encrypted(&self) -> bool {
+        self.key.is_some() || !self.keys.is_empty()
+    }
+}
+
+impl Default for ConfigurationOptions {
+    /// No keys, with rollback protection and caching in their default places
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+/// A configuration along with an Ed25519 signature over its bytes, or over
+/// its metadata and bytes when it carries metadata
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignedConfiguration {
+    /// Base64 encoded configuration JSON
+    configuration: String,
+    /// The name and version the signature vouches for
+    #[serde(default)]
+    metadata: Option<Metadata>,
+    /// Base64 encoded signature of the decoded configuration
+    signature: String,
 }
 
 #[derive(Deserialize)]
 pub struct HimitsuConfiguration {
     pub scanner: Scanner,
+    /// Which findings block a change and which only produce warnings
+    #[serde(default)]
+    pub policy: Policy,
+    /// How much of each found secret is included in responses
+    #[serde(default)]
+    pub redaction: Redaction,
+    /// Limits on the messages the daemon accepts from clients
+    #[serde(default)]
+    pub limits: MessageLimits,
+    /// How often the daemon checks the configuration's source for changes,
+    /// in seconds. When unset it only refreshes when asked to.
+    #[serde(default)]
+    pub refresh_interval_secs: Option<u64>,
     #[serde(skip)]
     source: Option<ConfigurationSource>,
+    /// When the configuration was fetched if it was loaded from the cache
+    #[serde(skip)]
+    cached_at: Option<SystemTime>,
 }
 
 impl From<reqwest::Error> for HimitsuError {
     fn from(err: reqwest::Error) -> Self {
-        HimitsuError::WebConfigError(err)
+        // The URL may hold a token, and these errors end up in logs and the
+        // daemon's status
+        HimitsuError::WebConfigError(err.without_url())
     }
 }
 
@@ -52,29 +222,159 @@ impl HimitsuConfiguration {
     pub fn default() -> Self {
         Self {
             scanner: Scanner::default(),
+            policy: Policy::default(),
+            redaction: Redaction::default(),
+            limits: MessageLimits::default(),
+            refresh_interval_secs: None,
             source: None,
+            cached_at: None,
         }
     }
 
-    pub fn refresh(&mut self) {
-        match &self.source {
-            Some(ConfigurationSource::Path(path)) => {
-                if let Ok(config) = HimitsuConfiguration::new_from_file(path.clone()) {
-                    *self = config;
-                }
-            }
-            Some(ConfigurationSource::Url(url, key)) => {
-                if let Ok(config) = HimitsuConfiguration::new_from_url(url.clone(), key.clone()) {
-                    *self = config;
+    /// Where the configuration came from and how stale it is
+    pub fn status(&self) -> Status {
+        let (configuration_source, max_staleness) = match &self.source {
+            Some(ConfigurationSource::Path(path)) => (path.clone(), None),
+            // The query is left out as it may hold a token
+            Some(ConfigurationSource::Url(url, options, _)) => (
+                url.split('?').next().unwrap_or_default().to_string(),
+                options.cache_dir.as_ref().map(|_| options.max_staleness),
+            ),
+            None => ("default".to_string(), None),
+        };
+
+        Status {
+            configuration_source,
+            cache_age_secs: self
+                .cached_at
+                .map(|at| at.elapsed().unwrap_or_default().as_secs()),
+            max_staleness_secs: max_staleness.map(|d| d.as_secs()),
+            refresh_interval_secs: self.refresh_interval().map(|d| d.as_secs()),
+            ..Default::default()
+        }
+    }
+
+    /// Problems with the configuration that should be fixed, such as expired
+    /// allowlist entries that are now being ignored
+    pub fn lint(&self) -> Vec<String> {
+        self.scanner.lint()
+    }
+
+    /// Scan a diff. Values are kept as found, see `redact`.
+    pub fn scan(&self, diff: String) -> ScanResults {
+        self.scanner.scan(&diff)
+    }
+
+    /// Scan a diff and decide on a response with the configured policy
+    pub fn check(&self, diff: String) -> HimitsuResponse {
+        self.policy.evaluate(self.scan(diff))
+    }
+
+    /// Scan whole files. Values are kept as found, see `redact`.
+    pub fn scan_files(&self, files: Vec<FileBlob>) -> ScanResults {
+        self.scanner.scan_files(&files)
+    }
+
+    /// Scan whole files and decide on a response with the configured policy
+    pub fn check_files(&self, files: Vec<FileBlob>) -> HimitsuResponse {
+        self.policy.evaluate(self.scan_files(files))
+    }
+
+    /// Redact found values as configured, for when results are shown or
+    /// written out
+    pub fn redact(&self, response: HimitsuResponse) -> HimitsuResponse {
+        self.redaction.apply_response(response)
+    }
+
+    /// Like `redact` for results that aren't part of a response
+    pub fn redact_results(&self, results: ScanResults) -> ScanResults {
+        self.redaction.apply(results)
+    }
+
+    /// How often the configuration should be refreshed in the background
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn source(&self) -> Option<ConfigurationSource> {
+        self.source.clone()
+    }
+
+    /// Load the configuration again from where it came from, returning
+    /// `None` if the server says it hasn't changed. Unlike the first load a
+    /// failed fetch is an error rather than falling back to the cache, as
+    /// the current configuration can be kept instead.
+    pub(crate) fn reload(source: ConfigurationSource) -> HResult<Option<Self>> {
+        match source {
+            ConfigurationSource::Path(path) => Self::new_from_file(path).map(Some),
+            ConfigurationSource::Url(url, options, validators) => {
+                match Self::fetch(&url, &validators)? {
+                    Some(fetched) => Self::from_fetched(url, *options, fetched, None).map(Some),
+                    None => {
+                        // The cached copy is what the server confirmed is
+                        // current, so it is as fresh as a new fetch
+                        if let Some(dir) = &options.cache_dir {
+                            if let Err(e) = ConfigCache::new(dir.clone(), &url).touch() {
+                                error!("Could not refresh the cached configuration: {e}");
+                            }
+                        }
+                        Ok(None)
+                    }
                 }
             }
-            _ => {
-                error!("This configuration does not have a source to refresh from");
+        }
+    }
+
+    /// Decrypt a configuration in either the envelope or the legacy format,
+    /// returning the metadata it was sealed with if any
+    fn decrypt_configuration(
+        data: Vec<u8>,
+        options: &ConfigurationOptions,
+    ) -> HResult<(Vec<u8>, Option<Metadata>)> {
+        if !Envelope::is_envelope(&data) {
+            let key = options.key.as_ref().ok_or_else(|| {
+                HimitsuError::CryptographyError(
+                    "Configuration is in the legacy format which needs a key without an id"
+                        .to_string(),
+                )
+            })?;
+            return Ok((Self::decrypt_legacy_configuration(data, key)?, None));
+        }
+
+        let envelope = Envelope::parse(&data)?;
+        let key = options
+            .keys
+            .get(&envelope.key_id)
+            .or(options.key.as_ref())
+            .ok_or_else(|| {
+                HimitsuError::CryptographyError(format!("No key with id {}", envelope.key_id))
+            })?;
+        let key = hex::decode(key.expose_secret())
+            .map_err(|_| HimitsuError::CryptographyError("Invalid key".to_string()))?;
+        let metadata = envelope.metadata.clone();
+        Ok((envelope.open(&key)?, metadata))
+    }
+
+    /// Make sure a configuration is the one we expect before using it
+    fn check_metadata(metadata: Option<&Metadata>, options: &ConfigurationOptions) -> HResult<()> {
+        match (metadata, &options.name) {
+            (Some(metadata), Some(name)) if &metadata.name != name => {
+                Err(HimitsuError::CryptographyError(format!(
+                    "Expected configuration {name} but was given {}",
+                    metadata.name
+                )))
             }
+            (None, Some(name)) => Err(HimitsuError::CryptographyError(format!(
+                "Configuration {name} must carry its name and version"
+            ))),
+            _ => Ok(()),
         }
     }
 
-    fn decrypt_configuration(data: Vec<u8>, key: &Secret<String>) -> HResult<Vec<u8>> {
+    /// Decrypt the original `nonce || ciphertext || tag` format
+    fn decrypt_legacy_configuration(data: Vec<u8>, key: &Secret<String>) -> HResult<Vec<u8>> {
         // Make sure the data is long enough to split off the nonce from the front
         if data.len() < NONCE_LEN {
             return Err(HimitsuError::CryptographyError(
@@ -117,37 +417,184 @@ impl HimitsuConfiguration {
         Ok(config)
     }
 
-    pub fn new_from_b64_string(config: String, key: Option<Secret<String>>) -> HResult<Self> {
-        let config = BASE64_STANDARD
-            .decode(config.as_bytes())
-            .map_err(|e| HimitsuError::EncodingError(e.to_string()))?;
+    /// Check the signature of a signed configuration and return the
+    /// configuration it wraps along with the metadata the signature covers.
+    /// Unsigned configurations are passed through unless a verification key
+    /// requires them to be signed, while a signed configuration can only be
+    /// used with a key to verify it.
+    fn verify_configuration(
+        data: Vec<u8>,
+        verification_key: Option<&String>,
+    ) -> HResult<(Vec<u8>, Option<Metadata>)> {
+        let signed: SignedConfiguration = match serde_json::from_slice(&data) {
+            Ok(signed) => signed,
+            Err(_) if verification_key.is_none() => return Ok((data, None)),
+            Err(_) => {
+                return Err(HimitsuError::CryptographyError(
+                    "Configuration is not signed".to_string(),
+                ))
+            }
+        };
 
-        if let Some(key) = key.as_ref() {
-            let data = Self::decrypt_configuration(config, &key)?;
-            Ok(serde_json::from_slice(&data)?)
-        } else {
-            Ok(serde_json::from_slice(&config)?)
-        }
+        let verification_key = verification_key.ok_or_else(|| {
+            HimitsuError::CryptographyError(
+                "Configuration is signed but no verification key is configured".to_string(),
+            )
+        })?;
+
+        let decode = |data: &str| {
+            BASE64_STANDARD
+                .decode(data.as_bytes())
+                .map_err(|e| HimitsuError::EncodingError(e.to_string()))
+        };
+        let config = decode(&signed.configuration)?;
+        let verification_key = hex::decode(verification_key)
+            .map_err(|_| HimitsuError::CryptographyError("Invalid verification key".to_string()))?;
+        let message = match &signed.metadata {
+            Some(metadata) => metadata.signed_message(&config)?,
+            None => config.clone(),
+        };
+        UnparsedPublicKey::new(&ED25519, verification_key)
+            .verify(&message, &decode(&signed.signature)?)
+            .map_err(|_| {
+                HimitsuError::CryptographyError("Invalid configuration signature".to_string())
+            })?;
+
+        Ok((config, signed.metadata))
     }
 
-    pub fn new_from_url(url: String, key: Option<Secret<String>>) -> HResult<Self> {
-        let config = reqwest::blocking::get(&url)?.text()?;
+    /// Decode, decrypt and verify a configuration
+    fn parse_configuration(config: &str, options: &ConfigurationOptions) -> HResult<Self> {
         // All configurations are encoded first
         let mut config = BASE64_STANDARD
             .decode(config.as_bytes())
             .map_err(|e| HimitsuError::EncodingError(e.to_string()))?;
 
         // If we were given a key, decrypt the configuration
-        if let Some(key) = key.as_ref() {
-            config = Self::decrypt_configuration(config, key)?;
+        let mut sealed = None;
+        if options.is_encrypted() {
+            (config, sealed) = Self::decrypt_configuration(config, options)?;
         }
 
-        // Convert the decrypted data into a string we can deserialize
-        let config = String::from_utf8(config.to_vec())
-            .map_err(|e| HimitsuError::EncodingError(e.to_string()))?;
+        // Anyone with the shared key can seal any metadata, so when there is
+        // a signature only the metadata it covers is used. Without a
+        // verification key the sealed metadata is all there is to go on.
+        let (config, metadata) =
+            Self::verify_configuration(config, options.verification_key.as_ref())?;
+        let metadata = match (metadata, sealed) {
+            (Some(metadata), Some(sealed)) if metadata != sealed => {
+                return Err(HimitsuError::CryptographyError(
+                    "Envelope metadata doesn't match the signed metadata".to_string(),
+                ))
+            }
+            (None, sealed) if options.verification_key.is_none() => sealed,
+            (metadata, _) => metadata,
+        };
+        Self::check_metadata(metadata.as_ref(), options)?;
+        let config = serde_json::from_slice(&config)?;
 
-        let mut config: HimitsuConfiguration = serde_json::from_str(&config)?;
-        config.source = Some(ConfigurationSource::Url(url, key));
+        // Only remember the version once we know the configuration is usable
+        if let Some(path) = &options.state_path {
+            AcceptedVersions::accept(path, metadata.as_ref())?;
+        }
+        Ok(config)
+    }
+
+    pub fn new_from_b64_string(config: String, options: ConfigurationOptions) -> HResult<Self> {
+        Self::parse_configuration(&config, &options)
+    }
+
+    /// Fetch a configuration, falling back to the last one fetched
+    /// successfully if the fetch fails so clients that start offline still
+    /// get a configuration. A body that can't be decrypted or verified, such
+    /// as a captive portal's login page, counts as a failed fetch.
+    pub fn new_from_url(url: String, options: ConfigurationOptions) -> HResult<Self> {
+        let error = match Self::fetch(&url, &Validators::default()) {
+            Ok(Some(fetched)) => {
+                match Self::from_fetched(url.clone(), options.clone(), fetched, None) {
+                    Ok(config) => return Ok(config),
+                    Err(e) => e,
+                }
+            }
+            Ok(None) => unreachable!("Only conditional requests are answered as unchanged"),
+            Err(e) => e.into(),
+        };
+
+        let cache = match &options.cache_dir {
+            Some(dir) => ConfigCache::new(dir.clone(), &url),
+            None => return Err(error),
+        };
+        match cache.load(options.max_staleness) {
+            Ok((body, cached_at)) => {
+                warn!("Using the cached configuration, fetching it failed: {error}");
+                let validators = Validators::default();
+                Self::from_fetched(url, options, Fetched { body, validators }, Some(cached_at))
+            }
+            Err(cache_error) => {
+                error!("No usable cached configuration: {cache_error}");
+                Err(error)
+            }
+        }
+    }
+
+    /// Fetch a configuration, asking the server to only send it if it has
+    /// changed since it returned the validators. Returns `None` if it hasn't.
+    fn fetch(url: &str, validators: &Validators) -> reqwest::Result<Option<Fetched>> {
+        let mut request = reqwest::blocking::Client::new().get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request.send()?;
+        let conditional = validators.etag.is_some() || validators.last_modified.is_some();
+        if conditional && response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let response = response.error_for_status()?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+
+        Ok(Some(Fetched {
+            body: response.text()?,
+            validators,
+        }))
+    }
+
+    /// Use a configuration fetched from a URL, caching it unless it came
+    /// from the cache. The cached copy is decrypted and verified again like a
+    /// fetched one.
+    fn from_fetched(
+        url: String,
+        options: ConfigurationOptions,
+        fetched: Fetched,
+        cached_at: Option<SystemTime>,
+    ) -> HResult<Self> {
+        let mut config = Self::parse_configuration(&fetched.body, &options)?;
+        if let (Some(dir), None) = (&options.cache_dir, cached_at) {
+            if let Err(e) = ConfigCache::new(dir.clone(), &url).store(&fetched.body) {
+                error!("Could not cache the configuration: {e}");
+            }
+        }
+
+        config.cached_at = cached_at;
+        config.source = Some(ConfigurationSource::Url(
+            url,
+            Box::new(options),
+            fetched.validators,
+        ));
         Ok(config)
     }
 }
@@ -155,6 +602,28 @@ impl HimitsuConfiguration {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    /// Options that don't read or write state outside of the test
+    fn isolated(options: ConfigurationOptions) -> ConfigurationOptions {
+        ConfigurationOptions {
+            state_path: None,
+            cache_dir: None,
+            ..options
+        }
+    }
+
+    #[test]
+    fn test_example_config() {
+        let config: Result<HimitsuConfiguration, _> =
+            serde_json::from_str(include_str!("../resources/config.ex.json"));
+
+        if let Err(e) = config.as_ref() {
+            println!("Error: {:?}", e);
+        }
+        assert!(config.is_ok());
+    }
 
     #[test]
     fn test_new_from_b64_string() {
@@ -191,8 +660,11 @@ mod tests {
 
         let config = HimitsuConfiguration::new_from_b64_string(
             EXAMPLE_CONFIG.to_string(),
-            Some(Secret::new(
-                "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+            isolated(ConfigurationOptions::new(
+                Some(Secret::new(
+                    "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+                )),
+                None,
             )),
         );
 
@@ -201,4 +673,288 @@ mod tests {
         }
         assert_eq!(config.is_ok(), true);
     }
+
+    const CONFIG: &[u8] = br#"{"scanner": {"regex": {}}}"#;
+
+    fn metadata(name: &str, version: u64) -> Metadata {
+        Metadata {
+            name: name.to_string(),
+            version,
+            issued_at: 0,
+        }
+    }
+
+    fn seal(key_id: &str, key: &[u8], metadata: &Metadata, config: &[u8]) -> String {
+        BASE64_STANDARD.encode(Envelope::seal(key_id, key, metadata, config).unwrap())
+    }
+
+    fn signing_key() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap()
+    }
+
+    /// Sign a configuration along with its metadata, as encrypt-config does
+    fn sign(metadata: &Metadata) -> Vec<u8> {
+        let message = metadata.signed_message(CONFIG).unwrap();
+        serde_json::json!({
+            "configuration": BASE64_STANDARD.encode(CONFIG),
+            "metadata": metadata,
+            "signature": BASE64_STANDARD.encode(signing_key().sign(&message)),
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let sealed = seal("new", &[2; 32], &metadata("default", 1), CONFIG);
+        let options =
+            |spec: String| isolated(ConfigurationOptions::from_key_spec(spec, None).unwrap());
+
+        let spec = format!("old:{},new:{}", "01".repeat(32), "02".repeat(32));
+        let options_with_both = options(spec);
+        assert_eq!(options_with_both.keys.len(), 2);
+        assert!(
+            HimitsuConfiguration::new_from_b64_string(sealed.clone(), options_with_both).is_ok()
+        );
+
+        // A key without an id is used for ids that aren't listed
+        let spec = format!("old:{},{}", "01".repeat(32), "02".repeat(32));
+        let options_with_default = options(spec);
+        assert!(options_with_default.key.is_some());
+        assert!(
+            HimitsuConfiguration::new_from_b64_string(sealed.clone(), options_with_default).is_ok()
+        );
+
+        let options_with_old = options(format!("old:{}", "01".repeat(32)));
+        assert!(HimitsuConfiguration::new_from_b64_string(sealed, options_with_old).is_err());
+
+        let (one, two) = ("01".repeat(32), "02".repeat(32));
+        for spec in [
+            String::new(),
+            format!("old:{one},"),
+            format!(":{one}"),
+            format!("old:{one},old:{two}"),
+            format!("{one},{two}"),
+            "old:nothex".to_string(),
+        ] {
+            assert!(ConfigurationOptions::from_key_spec(spec, None).is_err());
+        }
+
+        // Keys of the wrong length are refused, naming the entry
+        for short in ["01", &"01".repeat(16), &"01".repeat(33)] {
+            let spec = format!("old:{one},new:{short}");
+            let error = match ConfigurationOptions::from_key_spec(spec, None) {
+                Err(error) => error.to_string(),
+                Ok(_) => panic!("A {} character key was accepted", short.len()),
+            };
+            assert!(error.contains("Key entry 2"), "{error}");
+            assert!(!error.contains(short));
+        }
+    }
+
+    #[test]
+    fn test_configuration_metadata() {
+        let state_path = env::temp_dir().join(format!("himitsu-metadata-{}", std::process::id()));
+        let verification_key = hex::encode(signing_key().public_key().as_ref());
+        let mut options = isolated(ConfigurationOptions::new(
+            Some(Secret::new("01".repeat(32))),
+            Some(verification_key),
+        ));
+        options.state_path = Some(state_path.clone());
+        options.name = Some("production".to_string());
+        let parse = |options: &ConfigurationOptions, config: String| {
+            HimitsuConfiguration::new_from_b64_string(config, options.clone())
+        };
+        let signed = |name, version| {
+            let metadata = metadata(name, version);
+            seal("default", &[1; 32], &metadata, &sign(&metadata))
+        };
+
+        assert!(parse(&options, signed("production", 2)).is_ok());
+        // Another environment's configuration can't be substituted
+        assert!(parse(&options, signed("staging", 3)).is_err());
+        // Nor can an older configuration be rolled back to
+        assert!(matches!(
+            parse(&options, signed("production", 1)),
+            Err(HimitsuError::RollbackError(_, 1, 2))
+        ));
+        assert!(parse(&options, signed("production", 2)).is_ok());
+
+        // Metadata only sealed with the shared key isn't trusted once there
+        // is a signature
+        let resealed = seal(
+            "default",
+            &[1; 32],
+            &metadata("production", u64::MAX),
+            &sign(&metadata("production", 1)),
+        );
+        assert!(parse(&options, resealed).is_err());
+
+        // Once a version was accepted configurations must carry one
+        options.name = None;
+        let unversioned = BASE64_STANDARD.encode(
+            serde_json::json!({
+                "configuration": BASE64_STANDARD.encode(CONFIG),
+                "signature": BASE64_STANDARD.encode(signing_key().sign(CONFIG)),
+            })
+            .to_string(),
+        );
+        options.key = None;
+        assert!(parse(&options, unversioned).is_err());
+
+        std::fs::remove_file(state_path).unwrap();
+    }
+
+    #[test]
+    fn test_sealed_metadata() {
+        let state_path = env::temp_dir().join(format!("himitsu-sealed-{}", std::process::id()));
+        let mut options = isolated(ConfigurationOptions::new(
+            Some(Secret::new("01".repeat(32))),
+            None,
+        ));
+        options.state_path = Some(state_path.clone());
+        options.name = Some("production".to_string());
+        let parse =
+            |config: String| HimitsuConfiguration::new_from_b64_string(config, options.clone());
+        let sealed = |name, version| seal("default", &[1; 32], &metadata(name, version), CONFIG);
+
+        // Without a verification key the sealed metadata is checked instead
+        assert!(parse(sealed("production", 2)).is_ok());
+        assert!(parse(sealed("staging", 3)).is_err());
+        assert!(matches!(
+            parse(sealed("production", 1)),
+            Err(HimitsuError::RollbackError(_, 1, 2))
+        ));
+
+        std::fs::remove_file(state_path).unwrap();
+    }
+
+    #[test]
+    fn test_cached_configuration() {
+        let cache_dir = env::temp_dir().join(format!("himitsu-offline-{}", std::process::id()));
+        // Nothing listens on port 1 so fetching always fails
+        let url = "http://127.0.0.1:1/config?token=secret".to_string();
+        let options = ConfigurationOptions {
+            cache_dir: Some(cache_dir.clone()),
+            max_staleness: Duration::from_secs(60),
+            ..isolated(Default::default())
+        };
+        assert!(HimitsuConfiguration::new_from_url(url.clone(), options.clone()).is_err());
+
+        ConfigCache::new(cache_dir.clone(), &url)
+            .store(&BASE64_STANDARD.encode(r#"{"scanner": {"regex": {}}}"#))
+            .unwrap();
+        let config = HimitsuConfiguration::new_from_url(url.clone(), options.clone()).unwrap();
+        let status = config.status();
+        assert_eq!(status.configuration_source, "http://127.0.0.1:1/config");
+        assert!(status.cache_age_secs.is_some());
+        assert_eq!(status.max_staleness_secs, Some(60));
+
+        let options = ConfigurationOptions {
+            max_staleness: Duration::ZERO,
+            ..options
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(HimitsuConfiguration::new_from_url(url, options).is_err());
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_undecodable_configuration() {
+        // A captive portal answering with its login page
+        let (url, _) = testing::serve(1, "<html>Please log in</html>".to_string());
+
+        let cache_dir = env::temp_dir().join(format!("himitsu-portal-{}", std::process::id()));
+        let options = ConfigurationOptions {
+            cache_dir: Some(cache_dir.clone()),
+            ..isolated(Default::default())
+        };
+        ConfigCache::new(cache_dir.clone(), &url)
+            .store(&BASE64_STANDARD.encode(CONFIG))
+            .unwrap();
+
+        let config = HimitsuConfiguration::new_from_url(url.clone(), options).unwrap();
+        assert!(config.status().cache_age_secs.is_some());
+        // The login page didn't replace the cached configuration
+        let (cached, _) = ConfigCache::new(cache_dir.clone(), &url)
+            .load(Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(cached, BASE64_STANDARD.encode(CONFIG));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unchanged_configuration() {
+        let (url, requests) = testing::serve(2, BASE64_STANDARD.encode(CONFIG));
+        let cache_dir = env::temp_dir().join(format!("himitsu-unchanged-{}", std::process::id()));
+        let options = ConfigurationOptions {
+            cache_dir: Some(cache_dir.clone()),
+            ..isolated(Default::default())
+        };
+        let config = HimitsuConfiguration::new_from_url(url.clone(), options).unwrap();
+
+        // Age the cache as if the configuration was fetched an hour ago
+        let cache = ConfigCache::new(cache_dir.clone(), &url);
+        std::fs::File::options()
+            .write(true)
+            .open(
+                cache_dir
+                    .read_dir()
+                    .unwrap()
+                    .next()
+                    .unwrap()
+                    .unwrap()
+                    .path(),
+            )
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60 * 60))
+            .unwrap();
+        assert!(cache.load(Duration::from_secs(60)).is_err());
+
+        // The server saying it hasn't changed counts as fetching it again
+        let reloaded = HimitsuConfiguration::reload(config.source().unwrap()).unwrap();
+        assert!(reloaded.is_none());
+        assert!(requests.lock().unwrap()[1].contains("if-none-match: \"v1\""));
+        assert!(cache.load(Duration::from_secs(60)).is_ok());
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_signed_configuration() {
+        let key_pair = signing_key();
+        let verification_key = hex::encode(key_pair.public_key().as_ref());
+        let options = isolated(ConfigurationOptions::new(None, Some(verification_key)));
+
+        let config = br#"{"scanner": {"regex": {"AwsKey": "AKIA[0-9A-Z]{16}"}}}"#;
+        let sign = |config: &[u8], signed: &[u8]| {
+            let signed = serde_json::json!({
+                "configuration": BASE64_STANDARD.encode(config),
+                "signature": BASE64_STANDARD.encode(key_pair.sign(signed)),
+            });
+            BASE64_STANDARD.encode(signed.to_string())
+        };
+
+        let signed = sign(config, config);
+        assert!(HimitsuConfiguration::new_from_b64_string(signed.clone(), options.clone()).is_ok());
+        // A signature can't be trusted without a key to check it against
+        assert!(HimitsuConfiguration::new_from_b64_string(
+            signed,
+            isolated(ConfigurationOptions::default())
+        )
+        .is_err());
+
+        let forged = sign(br#"{"scanner": {"regex": {}}}"#, config);
+        assert!(HimitsuConfiguration::new_from_b64_string(forged, options.clone()).is_err());
+
+        let unsigned = BASE64_STANDARD.encode(config);
+        assert!(HimitsuConfiguration::new_from_b64_string(unsigned.clone(), options).is_err());
+        assert!(HimitsuConfiguration::new_from_b64_string(
+            unsigned,
+            isolated(ConfigurationOptions::default())
+        )
+        .is_ok());
+    }
 }
@@ -0,0 +1,270 @@
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{HResult, HimitsuError};
+
+/// Marks data as an envelope rather than the legacy `nonce || ciphertext`
+pub const MAGIC: &[u8; 4] = b"HMTS";
+/// Envelopes are sealed with this version, which carries `Metadata`
+pub const VERSION: u8 = 2;
+/// The oldest envelope version still accepted. Version 1 has no metadata.
+pub const MIN_VERSION: u8 = 1;
+
+/// Ciphers an envelope may be sealed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Aes256Gcm = 1,
+}
+
+impl TryFrom<u8> for Algorithm {
+    type Error = HimitsuError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Algorithm::Aes256Gcm),
+            _ => Err(invalid(&format!("Unknown algorithm {value}"))),
+        }
+    }
+}
+
+/// Identifies a configuration so it can't be swapped for another
+/// environment's configuration or rolled back to an older one. Anyone with
+/// the shared key can seal any metadata, so it is only trusted when it is
+/// also signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub name: String,
+    /// Increased every time the configuration is published
+    pub version: u64,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+}
+
+/// Put in front of what is signed along with metadata so the signature
+/// can't be mistaken for one over a bare configuration
+const SIGNED_METADATA_CONTEXT: &[u8] = b"himitsu signed metadata\0";
+
+impl Metadata {
+    /// `name length | name | version | issued at`, as laid out in envelopes
+    fn to_bytes(&self) -> HResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        push_string(&mut bytes, "Name", &self.name)?;
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&self.issued_at.to_be_bytes());
+        Ok(bytes)
+    }
+
+    /// What is signed to bind the metadata to a configuration
+    pub fn signed_message(&self, config: &[u8]) -> HResult<Vec<u8>> {
+        let mut message = SIGNED_METADATA_CONTEXT.to_vec();
+        message.append(&mut self.to_bytes()?);
+        message.extend_from_slice(config);
+        Ok(message)
+    }
+}
+
+fn invalid(reason: &str) -> HimitsuError {
+    HimitsuError::CryptographyError(format!("Invalid configuration envelope: {reason}"))
+}
+
+fn push_string(bytes: &mut Vec<u8>, what: &str, value: &str) -> HResult<()> {
+    let len = u8::try_from(value.len()).map_err(|_| invalid(&format!("{what} too long")))?;
+    bytes.push(len);
+    bytes.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// An encrypted configuration laid out as
+/// `magic | version | key id length | key id | metadata | algorithm | nonce | ciphertext`
+/// where the metadata is `name length | name | version | issued at` with
+/// both numbers as big endian `u64`s and is left out of version 1.
+/// Everything before the ciphertext is authenticated along with it, and the
+/// key id lets clients hold several keys so keys can be rotated gradually.
+#[derive(Debug)]
+pub struct Envelope {
+    pub version: u8,
+    pub key_id: String,
+    pub metadata: Option<Metadata>,
+    pub algorithm: Algorithm,
+    header: Vec<u8>,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn is_envelope(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> HResult<Self> {
+        let mut rest = data
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| invalid("Missing magic"))?;
+        let mut take = |len: usize| {
+            if rest.len() < len {
+                return Err(invalid("Truncated"));
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            Ok(taken)
+        };
+
+        let version = take(1)?[0];
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(invalid(&format!("Unsupported version {version}")));
+        }
+        let mut take_string = |what: &str| {
+            let len = take(1)?[0] as usize;
+            String::from_utf8(take(len)?.to_vec())
+                .map_err(|_| invalid(&format!("{what} is not UTF-8")))
+        };
+        let key_id = take_string("Key id")?;
+        let metadata = match version {
+            1 => None,
+            _ => Some(Metadata {
+                name: take_string("Name")?,
+                version: u64::from_be_bytes(take(8)?.try_into().unwrap()),
+                issued_at: u64::from_be_bytes(take(8)?.try_into().unwrap()),
+            }),
+        };
+        let algorithm = Algorithm::try_from(take(1)?[0])?;
+        let nonce = take(NONCE_LEN)?.try_into().unwrap();
+        let header = data[..data.len() - rest.len()].to_vec();
+
+        Ok(Self {
+            version,
+            key_id,
+            metadata,
+            algorithm,
+            header,
+            nonce,
+            ciphertext: rest.to_vec(),
+        })
+    }
+
+    fn header(
+        key_id: &str,
+        metadata: Option<&Metadata>,
+        algorithm: Algorithm,
+        nonce: &[u8],
+    ) -> HResult<Vec<u8>> {
+        let mut header = MAGIC.to_vec();
+        header.push(if metadata.is_some() { VERSION } else { 1 });
+        push_string(&mut header, "Key id", key_id)?;
+        if let Some(metadata) = metadata {
+            header.append(&mut metadata.to_bytes()?);
+        }
+        header.push(algorithm as u8);
+        header.extend_from_slice(nonce);
+        Ok(header)
+    }
+
+    fn key(key: &[u8]) -> HResult<LessSafeKey> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| HimitsuError::CryptographyError("Invalid key".to_string()))?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    /// Encrypt a configuration with a fresh random nonce, binding the
+    /// metadata to it
+    pub fn seal(
+        key_id: &str,
+        key: &[u8],
+        metadata: &Metadata,
+        plaintext: &[u8],
+    ) -> HResult<Vec<u8>> {
+        Self::seal_with(key_id, key, Some(metadata), plaintext)
+    }
+
+    fn seal_with(
+        key_id: &str,
+        key: &[u8],
+        metadata: Option<&Metadata>,
+        plaintext: &[u8],
+    ) -> HResult<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| HimitsuError::CryptographyError("Could not create nonce".to_string()))?;
+
+        let mut data = Self::header(key_id, metadata, Algorithm::Aes256Gcm, &nonce)?;
+        let mut in_out = plaintext.to_vec();
+        Self::key(key)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&data),
+                &mut in_out,
+            )
+            .map_err(|_| HimitsuError::CryptographyError("Could not encrypt".to_string()))?;
+
+        data.append(&mut in_out);
+        Ok(data)
+    }
+
+    pub fn open(self, key: &[u8]) -> HResult<Vec<u8>> {
+        let mut in_out = self.ciphertext;
+        let plaintext_len = Self::key(key)?
+            .open_in_place(
+                Nonce::assume_unique_for_key(self.nonce),
+                Aad::from(&self.header),
+                &mut in_out,
+            )
+            .map_err(|_| HimitsuError::CryptographyError("Could not decrypt config".to_string()))?
+            .len();
+
+        in_out.truncate(plaintext_len);
+        Ok(in_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(name: &str, version: u64) -> Metadata {
+        Metadata {
+            name: name.to_string(),
+            version,
+            issued_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_envelope() {
+        let key = [1; 32];
+        let sealed = Envelope::seal("2024-06", &key, &metadata("production", 3), b"{}").unwrap();
+        assert!(Envelope::is_envelope(&sealed));
+
+        let envelope = Envelope::parse(&sealed).unwrap();
+        assert_eq!(envelope.version, VERSION);
+        assert_eq!(envelope.key_id, "2024-06");
+        assert_eq!(envelope.metadata, Some(metadata("production", 3)));
+        assert_eq!(envelope.algorithm, Algorithm::Aes256Gcm);
+        assert_eq!(envelope.open(&key).unwrap(), b"{}");
+
+        let wrong_key = Envelope::parse(&sealed).unwrap().open(&[2; 32]);
+        assert!(wrong_key.is_err());
+
+        // The header is authenticated so neither the key id nor the
+        // metadata can be swapped
+        let mut tampered = sealed.clone();
+        tampered[6] = b'3';
+        assert!(Envelope::parse(&tampered).unwrap().open(&key).is_err());
+        let mut tampered = sealed.clone();
+        tampered[14] = b'P';
+        assert!(Envelope::parse(&tampered).unwrap().open(&key).is_err());
+
+        assert!(Envelope::parse(&sealed[..10]).is_err());
+
+        // Version 1 envelopes without metadata are still understood
+        let sealed = Envelope::seal_with("2024-06", &key, None, b"{}").unwrap();
+        let envelope = Envelope::parse(&sealed).unwrap();
+        assert_eq!(envelope.version, 1);
+        assert_eq!(envelope.metadata, None);
+        assert_eq!(envelope.open(&key).unwrap(), b"{}");
+    }
+}
@@ -11,6 +11,12 @@ pub enum HimitsuError {
     ConfigError(serde_json::Error),
     WebConfigError(reqwest::Error),
     CryptographyError(String),
+    /// The size of a message and the maximum allowed
+    MessageTooLargeError(usize, usize),
+    TimeoutError(String),
+    /// The configuration name, its version and the last accepted version
+    RollbackError(String, u64, u64),
+    CacheError(String),
 }
 
 impl std::fmt::Display for HimitsuError {
@@ -23,6 +29,18 @@ impl std::fmt::Display for HimitsuError {
             HimitsuError::ConfigError(e) => write!(f, "Config Error: {}", e),
             HimitsuError::WebConfigError(e) => write!(f, "Web Config Error: {}", e),
             HimitsuError::CryptographyError(e) => write!(f, "Cryptography Error: {}", e),
+            HimitsuError::MessageTooLargeError(size, max) => write!(
+                f,
+                "Message of {} bytes is larger than the maximum of {} bytes",
+                size, max
+            ),
+            HimitsuError::TimeoutError(e) => write!(f, "Timeout: {}", e),
+            HimitsuError::RollbackError(name, version, accepted) => write!(
+                f,
+                "Configuration {} version {} is older than the accepted version {}",
+                name, version, accepted
+            ),
+            HimitsuError::CacheError(e) => write!(f, "Cache Error: {}", e),
         }
     }
 }
@@ -1,8 +1,9 @@
 use std::ffi::{c_char, c_int, CStr};
 
-use secrecy::Secret;
-
-use crate::{config::HimitsuConfiguration, HimitsuInstance};
+use crate::{
+    config::{ConfigurationOptions, HimitsuConfiguration},
+    HimitsuInstance,
+};
 
 pub struct HimitsuRuntime {
     instance: HimitsuInstance,
@@ -62,6 +63,71 @@ pub unsafe extern "C" fn himitsu_start_with_url_and_key(
         Ok(s) => s.to_owned(),
     };
 
+    let options = match ConfigurationOptions::from_key_spec(key, None) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Invalid key: {e}");
+            return std::ptr::null();
+        }
+    };
+    start_from_url(socket_path, url, options)
+}
+
+/// Like `himitsu_start_with_url_and_key` but the configuration must also be
+/// signed by the Ed25519 key whose hex encoded public key is given, along
+/// with the given name, and is refused if it is older than the last one
+/// accepted. The verification key and name may be null to leave them
+/// unchecked, though a signed configuration is refused without a
+/// verification key. Without one the name and version are taken from the
+/// encrypted envelope, which anyone holding the shared key can write.
+///
+/// # Safety
+///
+/// Every argument must be null or a valid pointer to a NUL terminated string.
+/// Only the verification key and name may be null.
+#[no_mangle]
+pub unsafe extern "C" fn himitsu_start_with_options(
+    socket_path: *const c_char,
+    url: *const c_char,
+    key: *const c_char,
+    verification_key: *const c_char,
+    config_name: *const c_char,
+) -> *const HimitsuRuntime {
+    println!("Requested To Start Himitsu");
+    let optional = |ptr: *const c_char| match ptr.is_null() {
+        true => Ok(None),
+        false => CStr::from_ptr(ptr).to_str().map(|s| Some(s.to_owned())),
+    };
+
+    let (socket_path, url, key, verification_key, config_name) = match (
+        optional(socket_path),
+        optional(url),
+        optional(key),
+        optional(verification_key),
+        optional(config_name),
+    ) {
+        (Ok(Some(socket_path)), Ok(Some(url)), Ok(Some(key)), Ok(verification_key), Ok(name)) => {
+            (socket_path, url, key, verification_key, name)
+        }
+        _ => return std::ptr::null(),
+    };
+
+    let mut options = match ConfigurationOptions::from_key_spec(key, verification_key) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Invalid key: {e}");
+            return std::ptr::null();
+        }
+    };
+    options.name = config_name;
+    start_from_url(socket_path, url, options)
+}
+
+fn start_from_url(
+    socket_path: String,
+    url: String,
+    options: ConfigurationOptions,
+) -> *const HimitsuRuntime {
     let runtime = match tokio::runtime::Runtime::new() {
         Err(e) => {
             println!("Failed to create runtime: {:?}", e);
@@ -70,7 +136,7 @@ pub unsafe extern "C" fn himitsu_start_with_url_and_key(
         Ok(r) => r,
     };
 
-    let configuration = match HimitsuConfiguration::new_from_url(url, Some(Secret::new(key))) {
+    let configuration = match HimitsuConfiguration::new_from_url(url, options) {
         Ok(config) => config,
         Err(e) => {
             println!("Failed to fetch configuration: {:?}", e);
@@ -208,6 +274,41 @@ pub unsafe extern "C" fn himitsu_get_found_secrets(
     return_ptr
 }
 
+/// The daemon's status as JSON, including how stale a cached configuration
+/// is. The string must be freed with `himitsu_free_string`.
+///
+/// # Safety
+///
+/// `instance_ptr` must have been returned by one of the start functions.
+#[no_mangle]
+pub unsafe extern "C" fn himitsu_get_status(instance_ptr: *mut HimitsuRuntime) -> *const c_char {
+    let instance = Box::from_raw(instance_ptr);
+    let sender = instance.instance.term_sender.clone();
+    let (callback, receiver) = tokio::sync::oneshot::channel();
+    instance.runtime.spawn(async move {
+        sender
+            .send(crate::HimitsuClientServerMessage::FetchStatus { callback })
+            .await
+            .unwrap();
+    });
+
+    let return_ptr = match instance.runtime.block_on(receiver) {
+        Ok(status) => {
+            let status = serde_json::to_string(&status).unwrap();
+            std::ffi::CString::new(status).unwrap().into_raw()
+        }
+        Err(e) => {
+            println!("Failed to fetch the status: {e}");
+            std::ptr::null()
+        }
+    };
+
+    // We need to leak again here otherwise we will free the HimitsuRuntime
+    // when we're still using it
+    Box::leak(instance);
+    return_ptr
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn himitsu_clear_found_secrets(instance_ptr: *mut HimitsuRuntime) {
     println!("Clearing found secrets");
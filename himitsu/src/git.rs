@@ -0,0 +1,461 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    message::FileBlob,
+    scanners::diff::{self, LineKind},
+};
+
+/// Git's own heuristic: a NUL byte in the first 8000 bytes means binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+const LFS_POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+/// Modes of index entries that don't hold file contents
+const SYMLINK_MODE: &str = "120000";
+const SUBMODULE_MODE: &str = "160000";
+
+/// Run git with the given arguments and return what it printed
+pub fn run(args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// A file changed in a commit or the index, identified by its blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedFile {
+    pub path: String,
+    pub blob: String,
+}
+
+/// Parse `git diff --raw -z` output into the changed files that have
+/// contents, skipping deletions, symlinks and submodules. Renames and copies
+/// are reported under their new path.
+pub fn parse_raw_diff(output: &[u8]) -> Vec<ChangedFile> {
+    let mut fields = output
+        .split(|b| *b == 0)
+        .map(|f| String::from_utf8_lossy(f).to_string());
+    let mut files = Vec::new();
+
+    while let Some(header) = fields.next() {
+        // :<old mode> <new mode> <old blob> <new blob> <status>
+        let header: Vec<&str> = header.trim_start_matches(':').split(' ').collect();
+        if header.len() < 5 {
+            continue;
+        }
+        let (mode, blob, status) = (header[1], header[3], header[4]);
+
+        let mut path = fields.next().unwrap_or_default();
+        if status.starts_with('R') || status.starts_with('C') {
+            path = fields.next().unwrap_or_default();
+        }
+
+        if status.starts_with('D') || mode == SYMLINK_MODE || mode == SUBMODULE_MODE {
+            continue;
+        }
+
+        files.push(ChangedFile {
+            path,
+            blob: blob.to_string(),
+        });
+    }
+
+    files
+}
+
+/// A file that was left out of a scan and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: String,
+    /// Such as `binary`, reads as "<path> which is <reason>"
+    pub reason: &'static str,
+}
+
+/// Decide whether blob contents should be scanned, returning them as text
+/// if so or the reason they are skipped. Binaries and Git LFS pointers are
+/// skipped, while text that isn't valid UTF-8 is still scanned.
+pub fn scannable_text(content: Vec<u8>) -> Result<String, &'static str> {
+    let head = &content[..content.len().min(BINARY_CHECK_LEN)];
+    if head.contains(&0) {
+        return Err("binary");
+    }
+    if content.starts_with(LFS_POINTER_PREFIX) {
+        return Err("a Git LFS pointer");
+    }
+    Ok(String::from_utf8_lossy(&content).to_string())
+}
+
+/// Parse a `git cat-file --batch` header, `<blob> <type> <size>`, into the
+/// size of the contents that follow. `None` is returned only when Git says
+/// the blob is missing, anything else unexpected is an error.
+fn parse_blob_header(blob: &str, header: &str) -> io::Result<Option<usize>> {
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    match fields[..] {
+        [sha, "missing"] if sha == blob => Ok(None),
+        [sha, _, size] if sha == blob => size.parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected size in git cat-file header: {header:?}"),
+            )
+        }),
+        [] => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("git cat-file stopped before returning blob {blob}"),
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected git cat-file header for blob {blob}: {header:?}"),
+        )),
+    }
+}
+
+/// Read the contents of the given files from the object database with a
+/// single `git cat-file --batch` process, returning what can be scanned and
+/// what was skipped.
+pub fn read_blobs(files: &[ChangedFile]) -> io::Result<(Vec<FileBlob>, Vec<SkippedFile>)> {
+    let mut child = Command::new("git")
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Write the requests from another thread so a full stdout pipe can't
+    // deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let requests: String = files.iter().map(|f| format!("{}\n", f.blob)).collect();
+    let writer = std::thread::spawn(move || stdin.write_all(requests.as_bytes()));
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut blobs = Vec::new();
+    let mut skipped = Vec::new();
+    for file in files {
+        let mut header = String::new();
+        stdout.read_line(&mut header)?;
+        let size = match parse_blob_header(&file.blob, &header)? {
+            Some(size) => size,
+            None => {
+                skipped.push(SkippedFile {
+                    path: file.path.clone(),
+                    reason: "missing from the object database",
+                });
+                continue;
+            }
+        };
+
+        let mut content = vec![0; size];
+        stdout.read_exact(&mut content)?;
+        // Every blob is followed by a newline
+        stdout.read_exact(&mut [0])?;
+
+        match scannable_text(content) {
+            Ok(content) => blobs.push(FileBlob {
+                path: file.path.clone(),
+                content,
+                added_lines: None,
+            }),
+            Err(reason) => skipped.push(SkippedFile {
+                path: file.path.clone(),
+                reason,
+            }),
+        }
+    }
+
+    writer.join().unwrap()?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "git cat-file --batch failed: {status}"
+        )));
+    }
+    Ok((blobs, skipped))
+}
+
+/// The line numbers each file in a diff adds. Every file in the diff has an
+/// entry, so renames and mode changes map to no lines.
+pub fn added_lines(diff: &str) -> HashMap<String, Vec<usize>> {
+    diff::parse_diff(diff)
+        .into_iter()
+        .map(|file| {
+            let lines = file
+                .lines
+                .iter()
+                .filter(|line| line.kind == LineKind::Added)
+                .map(|line| line.number)
+                .collect();
+            (file.path, lines)
+        })
+        .collect()
+}
+
+/// One line of what Git passes to a pre-push hook on stdin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushedRef {
+    pub local_ref: String,
+    pub local_sha: String,
+    pub remote_ref: String,
+    pub remote_sha: String,
+}
+
+/// Parse the `<local ref> <local sha> <remote ref> <remote sha>` lines Git
+/// gives a pre-push hook
+pub fn parse_pushed_refs(input: &str) -> Vec<PushedRef> {
+    input
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some(PushedRef {
+                local_ref: parts.next()?.to_string(),
+                local_sha: parts.next()?.to_string(),
+                remote_ref: parts.next()?.to_string(),
+                remote_sha: parts.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+// Git uses an all zero object id for refs that don't exist on one side of
+// a push
+fn is_null(sha: &str) -> bool {
+    sha.chars().all(|c| c == '0')
+}
+
+/// Whether a commit is in the local object database
+fn has_commit(sha: &str) -> bool {
+    run(&["cat-file", "-e", &format!("{sha}^{{commit}}")]).is_ok()
+}
+
+/// The commits a push would add to the remote, oldest first. Deleting a
+/// ref pushes nothing. For a new ref, or a remote commit we haven't
+/// fetched, every commit not already on some remote is new.
+pub fn pushed_commits(pushed: &PushedRef) -> io::Result<Vec<String>> {
+    if is_null(&pushed.local_sha) {
+        return Ok(Vec::new());
+    }
+
+    let range = format!("{}..{}", pushed.remote_sha, pushed.local_sha);
+    let args = if is_null(&pushed.remote_sha) || !has_commit(&pushed.remote_sha) {
+        vec![
+            "rev-list",
+            "--reverse",
+            &pushed.local_sha,
+            "--not",
+            "--remotes",
+        ]
+    } else {
+        vec!["rev-list", "--reverse", &range]
+    };
+
+    let output = run(&args)?;
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .map(|sha| sha.to_string())
+        .collect())
+}
+
+/// A commit along with who made it and when
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub author: String,
+    /// Author date in strict ISO 8601
+    pub date: String,
+    /// Author date as a Unix timestamp, for ordering dates in different
+    /// time zones
+    pub timestamp: i64,
+}
+
+/// Parse `git log --format=%H%x00%an <%ae>%x00%aI%x00%at` output
+fn parse_commit_log(output: &str) -> Vec<CommitInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\0');
+            Some(CommitInfo {
+                sha: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                timestamp: fields.next()?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Every commit reachable from the given refs or ranges, or from all refs
+/// if none are given, oldest first. `since` takes anything `git log
+/// --since` does.
+pub fn history(revisions: &[String], since: Option<&str>) -> io::Result<Vec<CommitInfo>> {
+    let since = since.map(|since| format!("--since={since}"));
+    let mut args = vec!["log", "--reverse", "--format=%H%x00%an <%ae>%x00%aI%x00%at"];
+    args.extend(since.as_deref());
+    if revisions.is_empty() {
+        args.push("--all");
+    } else {
+        args.extend(revisions.iter().map(|r| r.as_str()));
+    }
+    args.push("--");
+
+    let output = run(&args)?;
+    Ok(parse_commit_log(&String::from_utf8_lossy(&output)))
+}
+
+/// The patch a commit introduces. Merges are shown against their first
+/// parent so only what the merge brought in is included.
+pub fn commit_patch(sha: &str) -> io::Result<String> {
+    let output = run(&[
+        "show",
+        "--format=",
+        "--patch",
+        "--no-color",
+        "--no-ext-diff",
+        "--diff-merges=first-parent",
+        sha,
+    ])?;
+    Ok(String::from_utf8_lossy(&output).to_string())
+}
+
+/// The contents of every file added or modified in the index, marked with
+/// the lines the index changes so the rest is only used as context, along
+/// with the files that can't be scanned
+pub fn staged_files() -> io::Result<(Vec<FileBlob>, Vec<SkippedFile>)> {
+    let output = run(&["diff", "--cached", "--raw", "-z", "-M", "--no-abbrev"])?;
+    let (mut files, skipped) = read_blobs(&parse_raw_diff(&output))?;
+
+    let diff = run(&[
+        "-c",
+        "core.quotePath=false",
+        "diff",
+        "--cached",
+        "-M",
+        "--unified=0",
+        "--no-color",
+        "--no-ext-diff",
+    ])?;
+    let mut added = added_lines(&String::from_utf8_lossy(&diff));
+    for file in files.iter_mut() {
+        // A path Git had to quote won't match, so scan all of that file
+        // rather than none of it
+        file.added_lines = added.remove(&file.path);
+    }
+    Ok((files, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_raw_diff() {
+        let output = concat!(
+            ":100644 100644 aaaa bbbb M\0src/main.rs\0",
+            ":000000 100644 0000 cccc A\0new.txt\0",
+            ":100644 000000 dddd 0000 D\0gone.txt\0",
+            ":100644 100644 eeee ffff R087\0old.rs\0renamed.rs\0",
+            ":000000 120000 0000 1111 A\0link\0",
+            ":000000 160000 0000 2222 A\0vendor/lib\0",
+        );
+
+        let files = parse_raw_diff(output.as_bytes());
+        let paths: Vec<(&str, &str)> = files
+            .iter()
+            .map(|f| (f.path.as_str(), f.blob.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("src/main.rs", "bbbb"),
+                ("new.txt", "cccc"),
+                ("renamed.rs", "ffff")
+            ]
+        );
+    }
+
+    const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+    #[test]
+    fn test_parse_pushed_refs() {
+        let input = format!(
+            "refs/heads/main 1111 refs/heads/main 2222\nrefs/heads/gone {NULL_SHA} refs/heads/gone 3333\n"
+        );
+        let pushed = parse_pushed_refs(&input);
+        assert_eq!(pushed.len(), 2);
+        assert_eq!(pushed[0].local_sha, "1111");
+        assert_eq!(pushed[0].remote_sha, "2222");
+        // Deleting a ref pushes no commits
+        assert!(pushed_commits(&pushed[1]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_commit_log() {
+        let output =
+            "abc\x00Jane <jane@example.com>\x002024-01-02T03:04:05+01:00\x001704161045\nbroken\n";
+        assert_eq!(
+            parse_commit_log(output),
+            vec![CommitInfo {
+                sha: "abc".to_string(),
+                author: "Jane <jane@example.com>".to_string(),
+                date: "2024-01-02T03:04:05+01:00".to_string(),
+                timestamp: 1704161045,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_blob_header() {
+        assert_eq!(
+            parse_blob_header("aaaa", "aaaa blob 12\n").unwrap(),
+            Some(12)
+        );
+        assert_eq!(parse_blob_header("aaaa", "aaaa missing\n").unwrap(), None);
+        assert_eq!(
+            parse_blob_header("aaaa", "").unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(parse_blob_header("aaaa", "aaaa blob").is_err());
+        assert!(parse_blob_header("aaaa", "aaaa blob twelve\n").is_err());
+        assert!(parse_blob_header("aaaa", "bbbb missing\n").is_err());
+    }
+
+    #[test]
+    fn test_scannable_text() {
+        assert_eq!(scannable_text(b"key = 1\n".to_vec()).unwrap(), "key = 1\n");
+        assert_eq!(
+            scannable_text(b"key = \xff\n".to_vec()).unwrap(),
+            "key = \u{fffd}\n"
+        );
+        assert!(scannable_text(b"PNG\0\x01".to_vec()).is_err());
+        let pointer = b"version https://git-lfs.github.com/spec/v1\noid sha256:abc\nsize 12\n";
+        assert!(scannable_text(pointer.to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_added_lines() {
+        let diff = "\
+diff --git a/config.py b/config.py
+--- a/config.py
++++ b/config.py
+@@ -2 +2 @@
+-KEY = None
++KEY = \"secret\"
+@@ -9,0 +10,2 @@ def main():
++    a = 1
++    b = 2
+diff --git a/old.py b/new.py
+similarity index 100%
+rename from old.py
+rename to new.py
+";
+        let added = added_lines(diff);
+        assert_eq!(added["config.py"], vec![2, 10, 11]);
+        assert!(added["new.py"].is_empty());
+    }
+}
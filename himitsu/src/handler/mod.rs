@@ -1,41 +1,178 @@
 mod handler;
+mod peer;
 
+use std::fs::{self, DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{UnixListener, UnixStream};
 use tokio::select;
 use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval_at, timeout, Instant, Interval, MissedTickBehavior};
 
+use crate::message::{HimitsuResponse, MessageLimits};
 use crate::{message, HimitsuClientServerMessage};
-pub use handler::HimitsuHandler;
+pub use handler::{HimitsuHandler, Session};
+pub(crate) use peer::current_uid;
 
-use crate::error::HResult;
+use crate::error::{HResult, HimitsuError};
+
+/// Read one request from the client and answer it. Returns false if the
+/// connection can't carry on.
+async fn handle_request(
+    handler: &HimitsuHandler,
+    session: &mut Session,
+    stream: &mut UnixStream,
+    limits: &MessageLimits,
+) -> HResult<bool> {
+    let (response, keep_open) = match message::parse_incoming_message(stream, limits).await {
+        Ok(message) => {
+            trace!("message: {:?}", message);
+            (handler.handle_message(session, message).await?, true)
+        }
+        // The whole frame was read so the connection can carry on, which
+        // lets newer clients fall back when we don't understand them. Read
+        // and framing errors fall through and close the connection.
+        Err(HimitsuError::IncomingMessageError(e)) => (
+            HimitsuResponse::Error(format!(
+                "Himitsu could not understand the message, it may be from a newer client: {e}"
+            )),
+            true,
+        ),
+        // The rest of the message is still waiting to be read so tell the
+        // client why and hang up
+        Err(e @ HimitsuError::MessageTooLargeError(..)) => {
+            (HimitsuResponse::Error(e.to_string()), false)
+        }
+        Err(e) => return Err(e),
+    };
+    trace!("handler: {:?}", response);
+    message::serialize_and_send_response(response, stream).await?;
+    Ok(keep_open)
+}
 
 async fn handle_client(handler: Arc<HimitsuHandler>, mut stream: UnixStream) -> HResult<()> {
+    let mut session = Session::default();
+    let limits = handler.limits().await;
     loop {
-        let message = message::parse_incoming_message(&mut stream).await?;
-        trace!("message: {:?}", message);
-        let response = handler.handle_message(message).await?;
-        trace!("handler: {:?}", response);
-        message::serialize_and_send_response(response, &mut stream).await?;
+        let request = handle_request(&handler, &mut session, &mut stream, &limits);
+        match timeout(limits.request_timeout(), request).await {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                return Err(HimitsuError::TimeoutError(format!(
+                    "Request took longer than {} seconds",
+                    limits.request_timeout_secs
+                )))
+            }
+        }
+    }
+}
+
+/// Only serve peers running as our own user, telling anyone else why they
+/// were turned away
+async fn accept_client(handler: Arc<HimitsuHandler>, mut stream: UnixStream) -> HResult<()> {
+    if let Err(e) = peer::check(&stream) {
+        println!("Rejecting connection: {e}");
+        return message::serialize_and_send_response(HimitsuResponse::Error(e), &mut stream).await;
+    }
+
+    handle_client(handler, stream).await
+}
+
+/// Create the directory the socket lives in, accessible only to us, and
+/// remove a socket left behind by a previous instance, which would otherwise
+/// make binding fail. A directory someone else could have created or can
+/// write to is refused, as they could swap the socket for their own.
+fn prepare_socket_path(path: &Path) -> HResult<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(parent)?;
+
+    let metadata = fs::metadata(parent)?;
+    if metadata.uid() != peer::current_uid() || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(HimitsuError::IoError(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Socket directory {} must be owned by us and only accessible to us (mode 0700)",
+                parent.display()
+            ),
+        )));
+    }
+
+    // Only sockets are removed so the path can't be used to delete other files
+    let is_socket = fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    if is_socket && std::os::unix::net::UnixStream::connect(path).is_err() {
+        debug!("Removing stale socket at {}", path.display());
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// Bind the socket clients connect to, only accessible to us. Must be called
+/// from within the runtime.
+pub fn bind(path: &Path) -> HResult<UnixListener> {
+    prepare_socket_path(path)?;
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn refresh_timer(period: Option<Duration>) -> Option<Interval> {
+    period.map(|period| {
+        // The configuration was just loaded so the first refresh waits a period
+        let mut timer = interval_at(Instant::now() + period, period);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        timer
+    })
+}
+
+async fn refresh_tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
     }
 }
 
+/// Refresh in the background so a slow fetch doesn't hold up clients
+fn spawn_refresh(handler: &Arc<HimitsuHandler>) {
+    let handler = handler.clone();
+    tokio::spawn(async move { handler.update_configuration().await });
+}
+
 pub async fn run(
     handler: HimitsuHandler,
-    socket_path: String,
+    listener: UnixListener,
     mut term_channel: Receiver<HimitsuClientServerMessage>,
 ) {
-    println!("Starting Himitsu at: {}", socket_path);
-    let listener = UnixListener::bind(socket_path).unwrap();
     let handler = Arc::new(handler);
+    let mut refresh = None;
 
     loop {
+        // A refreshed configuration may change how often to refresh
+        let period = handler.refresh_interval().await;
+        if period != refresh.as_ref().map(Interval::period) {
+            refresh = refresh_timer(period);
+        }
+
         select! {
             msg = term_channel.recv() => {
                 match msg {
                     Some(HimitsuClientServerMessage::Update) => {
                         println!("Updating Himitsu Configuration...");
-                        handler.update_configuration().await;
+                        spawn_refresh(&handler);
                     }
                     Some(HimitsuClientServerMessage::SilenceOnce) => {
                         println!("Silencing Next Check");
@@ -54,19 +191,26 @@ pub async fn run(
                         println!("Clearing Found Secrets");
                         handler.clear_found_secrets().await;
                     }
+                    Some(HimitsuClientServerMessage::FetchStatus{callback}) => {
+                        let _ = callback.send(handler.status().await);
+                    }
                     None | Some(HimitsuClientServerMessage::Shutdown) => {
                         println!("Channel is gone, shutting down.");
                         return
                     }
                 }
             },
+            _ = refresh_tick(&mut refresh) => {
+                debug!("Refreshing Himitsu Configuration");
+                spawn_refresh(&handler);
+            },
             v = listener.accept() => {
                 match v {
                     Ok(stream) => {
                         debug!("Got connection from: {:?}. Spawning task to handle.", stream.1);
                         let handler = handler.clone();
                         tokio::spawn(async move {
-                            match handle_client(handler, stream.0).await {
+                            match accept_client(handler, stream.0).await {
                                 Ok(_) => {}
                                 Err(e) => debug!("handler: {:?}", e),
                             }
@@ -82,3 +226,46 @@ pub async fn run(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HimitsuConfiguration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_oversized_message() {
+        let handler = Arc::new(HimitsuHandler::new(HimitsuConfiguration::default()));
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let task = tokio::spawn(handle_client(handler, server));
+
+        client.write_u32(u32::MAX).await.unwrap();
+        let len = client.read_u32().await.unwrap();
+        let mut buf = vec![0; len as usize];
+        client.read_exact(&mut buf).await.unwrap();
+
+        let response: HimitsuResponse = serde_json::from_slice(&buf).unwrap();
+        assert!(matches!(response, HimitsuResponse::Error(e) if e.contains("maximum")));
+        // The daemon hangs up instead of trying to read the rest
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_prepare_socket_path() {
+        let dir = std::env::temp_dir().join(format!("himitsu-socket-{}", std::process::id()));
+        let path = dir.join("private/himitsu.sock");
+        assert!(prepare_socket_path(&path).is_ok());
+
+        // Other users could replace the socket in a shared directory
+        fs::set_permissions(dir.join("private"), Permissions::from_mode(0o755)).unwrap();
+        assert!(prepare_socket_path(&path).is_err());
+        fs::set_permissions(dir.join("private"), Permissions::from_mode(0o700)).unwrap();
+
+        // Anything other than a socket is left alone
+        fs::write(&path, "not a socket").unwrap();
+        assert!(prepare_socket_path(&path).is_ok());
+        assert!(path.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
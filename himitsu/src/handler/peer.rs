@@ -0,0 +1,70 @@
+use tokio::net::UnixStream;
+
+/// Only the user running the daemon may connect to it. The socket is private
+/// to that user as well, so this guards against its permissions being
+/// loosened.
+fn allows(uid: u32) -> bool {
+    uid == current_uid()
+}
+
+/// Check the credentials of the process on the other end of a connection,
+/// logging which process it was
+pub fn check(stream: &UnixStream) -> Result<(), String> {
+    let cred = stream
+        .peer_cred()
+        .map_err(|e| format!("Could not read peer credentials: {e}"))?;
+    let pid = cred
+        .pid()
+        .map(|pid| pid.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let executable = cred
+        .pid()
+        .and_then(executable)
+        .unwrap_or_else(|| "unknown".to_string());
+    let peer = format!(
+        "pid {pid} ({executable}) running as uid {} gid {}",
+        cred.uid(),
+        cred.gid()
+    );
+
+    if allows(cred.uid()) {
+        debug!("Accepted connection from {peer}");
+        Ok(())
+    } else {
+        Err(format!("{peer} is not allowed to use this Himitsu daemon"))
+    }
+}
+
+pub(crate) fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+#[cfg(target_os = "linux")]
+fn executable(pid: i32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{pid}/exe"))
+        .ok()
+        .map(|path| path.display().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn executable(_pid: i32) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        assert!(allows(current_uid()));
+        assert!(!allows(current_uid().wrapping_add(1)));
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let (client, _server) = UnixStream::pair().unwrap();
+        assert!(check(&client).is_ok());
+    }
+}
@@ -1,11 +1,22 @@
+mod cache;
 pub mod config;
+pub mod envelope;
 mod error;
 pub mod ffi;
+pub mod git;
 mod handler;
 pub mod message;
+pub mod output;
+mod policy;
+mod redaction;
+mod rollback;
 mod scanners;
+pub mod socket;
+pub mod storage;
+#[cfg(test)]
+mod testing;
 
-pub use scanners::ScanResults;
+pub use scanners::{FindingState, ScanResult, ScanResults, Severity};
 
 use error::HResult;
 use error::HimitsuError;
@@ -17,6 +28,7 @@ use tokio::task::JoinHandle;
 extern crate log;
 
 use std::env;
+use std::path::Path;
 use std::process;
 
 pub enum HimitsuClientServerMessage {
@@ -29,6 +41,9 @@ pub enum HimitsuClientServerMessage {
         callback: tokio::sync::oneshot::Sender<ScanResults>,
     },
     ClearFoundSecrets,
+    FetchStatus {
+        callback: tokio::sync::oneshot::Sender<message::Status>,
+    },
     Shutdown,
 }
 
@@ -60,22 +75,28 @@ pub fn start_himitsu(
 ) -> HResult<HimitsuInstance> {
     let _ = env_logger::try_init();
 
-    let socket_path = if let Some(path) = socket_path {
+    let socket_path = if let Some(path) = socket::resolve(socket_path) {
         path
     } else {
+        // Sockets must be in a directory of our own
         let mut socket = env::temp_dir();
+        socket.push(format!("himitsu-{}", handler::current_uid()));
         socket.push(format!("himitsu.{}", process::id()));
         socket.to_string_lossy().to_string()
     };
 
-    debug!("Starting Himitsu at: {socket_path}");
+    println!("Starting Himitsu at: {socket_path}");
+    let listener = {
+        let _runtime = runtime.enter();
+        handler::bind(Path::new(&socket_path))?
+    };
 
     let (term_sender, term_receiver) = tokio::sync::mpsc::channel::<HimitsuClientServerMessage>(5);
 
     let handler = handler::HimitsuHandler::new(configuration);
 
     let handle = runtime.spawn(async move {
-        handler::run(handler, socket_path, term_receiver).await;
+        handler::run(handler, listener, term_receiver).await;
     });
 
     Ok(HimitsuInstance {
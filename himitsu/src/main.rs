@@ -1,4 +1,4 @@
-use himitsu::config::HimitsuConfiguration;
+use himitsu::config::{self, HimitsuConfiguration};
 
 use std::env;
 
@@ -9,14 +9,17 @@ async fn main() {
     let args: Vec<String> = env::args().collect();
 
     let socket_path = args.get(1).map(|x| x.to_owned());
-    let config_path = args.get(2).map(|x| x.to_owned()).unwrap_or_default();
+    // Resolved the same way as the shim's local fallback so both scan with
+    // the same configuration
+    let config_path = config::resolve_path(args.get(2).map(|x| x.to_owned()));
 
-    let config = match HimitsuConfiguration::new_from_file(config_path) {
-        Ok(c) => c,
-        Err(e) => {
+    let config = match config_path.map(HimitsuConfiguration::new_from_file) {
+        Some(Ok(c)) => c,
+        Some(Err(e)) => {
             eprintln!("Using default configuration because error reading configuration file: {}", e);
             HimitsuConfiguration::default()
         }
+        None => HimitsuConfiguration::default(),
     };
 
     let handle = Handle::current();
@@ -1,36 +1,299 @@
+use std::time::Duration;
+
 use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::UnixStream,
+    time::timeout,
 };
 
-use crate::{error::HResult, HimitsuError, ScanResults};
+use crate::{error::HResult, HimitsuError, ScanResult, ScanResults};
+
+/// The version of the socket protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest protocol version the daemon still accepts
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The client understands `HimitsuResponse::Warnings`
+pub const CAPABILITY_WARNINGS: &str = "warnings";
+/// The client understands `HimitsuResponse::Informational`
+pub const CAPABILITY_INFORMATIONAL: &str = "informational";
+
+/// The daemon accepts `HimitsuMessage::ScanFiles`
+pub const CAPABILITY_SCAN_FILES: &str = "scan-files";
+/// The daemon accepts `HimitsuMessage::Status`
+pub const CAPABILITY_STATUS: &str = "status";
+
+/// Every capability this build supports
+pub const CAPABILITIES: &[&str] = &[
+    CAPABILITY_WARNINGS,
+    CAPABILITY_INFORMATIONAL,
+    CAPABILITY_SCAN_FILES,
+    CAPABILITY_STATUS,
+];
+
+/// The full contents of a file to scan
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FileBlob {
+    pub path: String,
+    pub content: String,
+    /// The line numbers a change added, when only those should be scanned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_lines: Option<Vec<usize>>,
+}
+
+/// What the daemon is running with
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    /// Where the configuration came from: `default`, a file or a URL
+    pub configuration_source: String,
+    /// How long ago the configuration in use was fetched, only set when the
+    /// fetch failed and a cached copy is being used instead
+    pub cache_age_secs: Option<u64>,
+    /// How old a cached configuration may be before it is no longer used
+    pub max_staleness_secs: Option<u64>,
+    /// How often the configuration is refreshed in the background
+    pub refresh_interval_secs: Option<u64>,
+    /// How long ago the configuration was last refreshed successfully
+    pub last_refresh_secs: Option<u64>,
+    /// Why the last refresh failed, cleared once a refresh succeeds
+    pub refresh_error: Option<String>,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let duration = |secs| humantime::format_duration(Duration::from_secs(secs));
+        write!(f, "Configuration: {}", self.configuration_source)?;
+        if let Some(age) = self.cache_age_secs {
+            write!(
+                f,
+                "\nUsing a cached configuration fetched {} ago",
+                duration(age)
+            )?;
+        }
+        if let Some(max_staleness) = self.max_staleness_secs {
+            write!(
+                f,
+                "\nCached configurations are used for up to {}",
+                duration(max_staleness)
+            )?;
+        }
+        if let Some(interval) = self.refresh_interval_secs {
+            write!(f, "\nRefreshed every {}", duration(interval))?;
+        }
+        if let Some(age) = self.last_refresh_secs {
+            write!(f, "\nLast refreshed {} ago", duration(age))?;
+        }
+        if let Some(e) = &self.refresh_error {
+            write!(f, "\nThe last refresh failed: {e}")?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum HimitsuMessage {
-    ScanCodeDiff { diff: String },
+    /// Sent by clients before anything else so the daemon knows which
+    /// responses they understand. Clients that never say hello are treated
+    /// as predating the handshake. The shape of this message must never
+    /// change so any two versions can negotiate.
+    Hello {
+        protocol_version: u32,
+        client_name: String,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+    ScanCodeDiff {
+        diff: String,
+    },
+    /// Scan whole files, such as the staged contents of changed files
+    ScanFiles {
+        files: Vec<FileBlob>,
+    },
+    Status,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum HimitsuResponse {
+    /// The reply to a compatible `Hello`, listing the capabilities both sides
+    /// support
+    Hello {
+        protocol_version: u32,
+        server_name: String,
+        capabilities: Vec<String>,
+    },
     Clean,
     SecretsFound(ScanResults),
     SecretsFoundSilent(ScanResults),
+    /// Secrets were found but none are severe enough to block the change
+    Warnings(ScanResults),
+    /// Only findings that should not block the change were found, such as
+    /// secrets being removed by a diff or suppressed by an inline comment
+    Informational(ScanResults),
     Error(String),
+    Status(Status),
+}
+
+impl HimitsuResponse {
+    // Higher ranked responses win when responses are merged
+    fn rank(&self) -> u8 {
+        match self {
+            HimitsuResponse::Hello { .. } | HimitsuResponse::Status(_) | HimitsuResponse::Clean => {
+                0
+            }
+            HimitsuResponse::Informational(_) => 1,
+            HimitsuResponse::Warnings(_) => 2,
+            HimitsuResponse::SecretsFoundSilent(_) => 3,
+            HimitsuResponse::SecretsFound(_) => 4,
+            HimitsuResponse::Error(_) => 5,
+        }
+    }
+
+    fn into_results(self) -> ScanResults {
+        match self {
+            HimitsuResponse::SecretsFound(results)
+            | HimitsuResponse::SecretsFoundSilent(results)
+            | HimitsuResponse::Warnings(results)
+            | HimitsuResponse::Informational(results) => results,
+            _ => ScanResults::new(),
+        }
+    }
+
+    /// Change every finding in the response, such as to record which commit
+    /// it came from
+    pub fn map_results(self, f: impl FnMut(ScanResult) -> ScanResult) -> HimitsuResponse {
+        match self {
+            HimitsuResponse::SecretsFound(r) => {
+                HimitsuResponse::SecretsFound(r.into_iter().map(f).collect())
+            }
+            HimitsuResponse::SecretsFoundSilent(r) => {
+                HimitsuResponse::SecretsFoundSilent(r.into_iter().map(f).collect())
+            }
+            HimitsuResponse::Warnings(r) => {
+                HimitsuResponse::Warnings(r.into_iter().map(f).collect())
+            }
+            HimitsuResponse::Informational(r) => {
+                HimitsuResponse::Informational(r.into_iter().map(f).collect())
+            }
+            response => response,
+        }
+    }
+
+    /// Combine the responses to several requests that were part of the same
+    /// check into one, keeping the most serious outcome and every finding.
+    pub fn merge(self, other: HimitsuResponse) -> HimitsuResponse {
+        let (winner, loser) = if other.rank() > self.rank() {
+            (other, self)
+        } else {
+            (self, other)
+        };
+
+        let mut results = loser.into_results();
+        match winner {
+            HimitsuResponse::SecretsFound(r) => {
+                results.extend(r);
+                HimitsuResponse::SecretsFound(results)
+            }
+            HimitsuResponse::SecretsFoundSilent(r) => {
+                results.extend(r);
+                HimitsuResponse::SecretsFoundSilent(results)
+            }
+            HimitsuResponse::Warnings(r) => {
+                results.extend(r);
+                HimitsuResponse::Warnings(results)
+            }
+            HimitsuResponse::Informational(r) => {
+                results.extend(r);
+                HimitsuResponse::Informational(results)
+            }
+            response => response,
+        }
+    }
+}
+
+/// Bounds on what a peer may send so a misbehaving local process can't make
+/// the daemon allocate huge buffers or hold a connection open forever.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct MessageLimits {
+    /// The largest message accepted, in bytes
+    pub max_message_size: usize,
+    /// How long a single read may wait for data, in seconds
+    pub read_timeout_secs: u64,
+    /// How long reading, handling and answering a request may take, in seconds
+    pub request_timeout_secs: u64,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 16 * 1024 * 1024,
+            read_timeout_secs: 10,
+            request_timeout_secs: 60,
+        }
+    }
+}
+
+impl MessageLimits {
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+}
+
+/// Run a read, failing if it takes longer than the read timeout
+async fn read_with_timeout<T>(
+    limits: &MessageLimits,
+    read: impl std::future::Future<Output = std::io::Result<T>>,
+) -> HResult<T> {
+    match timeout(limits.read_timeout(), read).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(HimitsuError::TimeoutError(format!(
+            "No data received for {} seconds",
+            limits.read_timeout_secs
+        ))),
+    }
 }
 
 /// Read a message from a Himitsu unix socket and parse it into the provided
 /// message type.
-pub async fn parse_incoming_message<T>(mut stream: &mut UnixStream) -> HResult<T>
+pub async fn parse_incoming_message<T>(
+    stream: &mut UnixStream,
+    limits: &MessageLimits,
+) -> HResult<T>
 where
     T: DeserializeOwned,
 {
-    // Read length of message
-    let len = AsyncReadExt::read_u32(stream).await?;
-    let mut buf = vec![0; len as usize];
-    AsyncReadExt::read_exact(&mut stream, &mut buf).await?;
+    // Read length of message and check it before allocating anything
+    let len = read_with_timeout(limits, stream.read_u32()).await? as usize;
+    if len > limits.max_message_size {
+        return Err(HimitsuError::MessageTooLargeError(
+            len,
+            limits.max_message_size,
+        ));
+    }
+
+    // Every read has to make progress within the read timeout so a client
+    // can't hold the connection by stalling part way through a message
+    let mut buf = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        let read = read_with_timeout(limits, stream.read(&mut buf[filled..])).await?;
+        // Only a complete frame that fails to parse is reported as an
+        // IncomingMessageError, the connection can't be reused after this
+        if read == 0 {
+            return Err(HimitsuError::IoError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("Message truncated after {filled} of {len} bytes"),
+            )));
+        }
+        filled += read;
+    }
 
     let message: T = serde_json::from_slice(&buf)
         .map_err(|e| HimitsuError::IncomingMessageError(e.to_string()))?;
@@ -48,3 +311,80 @@ pub async fn serialize_and_send_response<T: serde::Serialize>(
     stream.write_all(response.as_bytes()).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> MessageLimits {
+        MessageLimits {
+            max_message_size: 64,
+            read_timeout_secs: 1,
+            request_timeout_secs: 1,
+        }
+    }
+
+    async fn parse(frame: &[u8], close: bool) -> HResult<HimitsuMessage> {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(frame).await.unwrap();
+        if close {
+            drop(client);
+            parse_incoming_message(&mut server, &limits()).await
+        } else {
+            // Keep the client open so the server has to wait on it
+            let result = parse_incoming_message(&mut server, &limits()).await;
+            drop(client);
+            result
+        }
+    }
+
+    fn frame(len: u32, body: &[u8]) -> Vec<u8> {
+        let mut frame = len.to_be_bytes().to_vec();
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_frames() {
+        let body = br#"{"ScanCodeDiff":{"diff":"+x"}}"#;
+        let message = parse(&frame(body.len() as u32, body), true).await;
+        assert!(matches!(message, Ok(HimitsuMessage::ScanCodeDiff { .. })));
+
+        let truncated = parse(&frame(body.len() as u32, &body[..10]), true).await;
+        assert!(matches!(truncated, Err(HimitsuError::IoError(_))));
+
+        let oversized = parse(&frame(u32::MAX, b""), true).await;
+        assert!(matches!(
+            oversized,
+            Err(HimitsuError::MessageTooLargeError(_, 64))
+        ));
+
+        let malformed = parse(&frame(5, b"{nope"), true).await;
+        assert!(matches!(
+            malformed,
+            Err(HimitsuError::IncomingMessageError(_))
+        ));
+
+        let stalled = parse(&frame(body.len() as u32, &body[..10]), false).await;
+        assert!(matches!(stalled, Err(HimitsuError::TimeoutError(_))));
+    }
+
+    #[test]
+    fn test_merge() {
+        use crate::{scanners::Location, ScanResult};
+
+        let finding = |name| ScanResult::new("Regex", name, name, Location::default());
+        let warnings = HimitsuResponse::Warnings(ScanResults::from([finding("GenericSecret")]));
+        let found = HimitsuResponse::SecretsFound(ScanResults::from([finding("AwsKey")]));
+
+        match HimitsuResponse::Clean.merge(warnings).merge(found) {
+            HimitsuResponse::SecretsFound(results) => assert_eq!(results.len(), 2),
+            r => panic!("Expected secrets found, got {:?}", r),
+        }
+        let error = HimitsuResponse::Error("broken".to_string());
+        assert!(matches!(
+            error.merge(HimitsuResponse::Clean),
+            HimitsuResponse::Error(_)
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt::Write;
+
+use super::sorted;
+use crate::{FindingState, ScanResult, ScanResults};
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn details(result: &ScanResult) -> String {
+    let mut details = format!(
+        "[{}] {} was found by system {}",
+        result.severity, result.name, result.system
+    );
+    if let Some(location) = result.location() {
+        details.push_str(&format!(" at {location}"));
+    }
+    if let Some(commit) = &result.commit {
+        details.push_str(&format!(" in commit {commit}"));
+    }
+    if let Some(remediation) = &result.remediation {
+        details.push_str(&format!("\nRemediation: {remediation}"));
+    }
+    details
+}
+
+/// Every finding becomes a test case. Active findings fail, anything else
+/// is reported as skipped so it is visible without failing the build.
+pub fn render(results: &ScanResults) -> String {
+    let results = sorted(results);
+    let failures = results.iter().filter(|r| r.is_active()).count();
+    let skipped = results.len() - failures;
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"himitsu\" tests=\"{}\" failures=\"{failures}\">",
+        results.len()
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"himitsu\" tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\">",
+        results.len()
+    );
+
+    for result in results {
+        let classname = result.path.as_deref().unwrap_or("input");
+        let name = match result.location() {
+            Some(location) => format!("{} at {location}", result.name),
+            None => result.name.clone(),
+        };
+        let _ = writeln!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\">",
+            escape(classname),
+            escape(&name)
+        );
+
+        let details = escape(&details(result));
+        match result.state {
+            FindingState::Active => {
+                let _ = writeln!(
+                    xml,
+                    "      <failure type=\"{}\" message=\"{} found\">{details}</failure>",
+                    result.severity,
+                    escape(&result.name)
+                );
+            }
+            state => {
+                let _ = writeln!(
+                    xml,
+                    "      <skipped message=\"{state:?}\">{details}</skipped>"
+                );
+            }
+        }
+        let _ = writeln!(xml, "    </testcase>");
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
@@ -0,0 +1,111 @@
+mod junit;
+mod sarif;
+
+use std::str::FromStr;
+
+use crate::{ScanResult, ScanResults};
+
+/// Machine readable formats scan results can be rendered in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The `ScanResults` set as JSON
+    Json,
+    /// SARIF 2.1.0 for code scanning tools
+    Sarif,
+    /// JUnit XML for CI test reports
+    Junit,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            "junit" => Ok(Format::Junit),
+            _ => Err(format!("Unknown output format: {s}")),
+        }
+    }
+}
+
+impl Format {
+    pub fn render(&self, results: &ScanResults) -> String {
+        match self {
+            Format::Json => serde_json::to_string_pretty(results).unwrap(),
+            Format::Sarif => sarif::render(results),
+            Format::Junit => junit::render(results),
+        }
+    }
+}
+
+// Results are a set so they are sorted to keep the output stable between runs
+fn sorted(results: &ScanResults) -> Vec<&ScanResult> {
+    let mut results: Vec<&ScanResult> = results.iter().collect();
+    results.sort();
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scanners::Location, FindingState, Severity};
+
+    fn results() -> ScanResults {
+        let location = Location {
+            path: Some("src/config.rs".to_string()),
+            line: 12,
+            column: 5,
+        };
+        let mut active = ScanResult::new("Regex", "AwsKey", "AKIA****WXYZ", location.clone());
+        active.severity = Severity::Critical;
+        active.remediation = Some("Rotate the key".to_string());
+        active.keyed_hash = true;
+
+        let mut suppressed = ScanResult::new("Regex", "GenericSecret", "hunter2", location);
+        suppressed.remediation = Some("Move it to <vault> & rotate".to_string());
+        suppressed.state = FindingState::Suppressed;
+
+        ScanResults::from([active, suppressed])
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!("SARIF".parse::<Format>(), Ok(Format::Sarif));
+        assert!("xml".parse::<Format>().is_err());
+
+        let json: ScanResults = serde_json::from_str(&Format::Json.render(&results())).unwrap();
+        assert_eq!(json, results());
+
+        let sarif: serde_json::Value =
+            serde_json::from_str(&Format::Sarif.render(&results())).unwrap();
+        assert_eq!(sarif["version"], "2.1.0");
+        let sarif_results = sarif["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(sarif_results.len(), 2);
+        assert_eq!(sarif_results[0]["ruleId"], "AwsKey");
+        assert_eq!(sarif_results[0]["level"], "error");
+        let region = &sarif_results[0]["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startLine"], 12);
+        assert_eq!(region["startColumn"], 5);
+        assert_eq!(sarif_results[1]["suppressions"][0]["kind"], "inSource");
+        // Only keyed fingerprints are published
+        assert!(sarif_results[0]["partialFingerprints"].is_object());
+        assert!(sarif_results[1].get("partialFingerprints").is_none());
+        assert_eq!(
+            sarif["runs"][0]["tool"]["driver"]["rules"][0]["id"],
+            "AwsKey"
+        );
+
+        let mut removed = results().into_iter().next().unwrap();
+        removed.state = FindingState::Removed;
+        let sarif: serde_json::Value =
+            serde_json::from_str(&Format::Sarif.render(&ScanResults::from([removed]))).unwrap();
+        let removed = &sarif["runs"][0]["results"][0];
+        assert_eq!(removed["kind"], "informational");
+        assert_eq!(removed["level"], "none");
+
+        let junit = Format::Junit.render(&results());
+        assert!(junit.contains(r#"tests="2" failures="1" skipped="1""#));
+        assert!(junit.contains("Move it to &lt;vault&gt; &amp; rotate"));
+    }
+}
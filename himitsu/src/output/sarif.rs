@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use super::sorted;
+use crate::{FindingState, ScanResult, ScanResults, Severity};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+fn level(result: &ScanResult) -> &'static str {
+    match result.state {
+        // SARIF requires informational results to have no level
+        FindingState::Removed => return "none",
+        FindingState::Warning => return "warning",
+        FindingState::Active | FindingState::Suppressed => {}
+    }
+
+    match result.severity {
+        Severity::Critical | Severity::High => "error",
+        Severity::Medium => "warning",
+        Severity::Low | Severity::Info => "note",
+    }
+}
+
+// SARIF describes each rule once in the tool section and results refer to
+// it by id. Values are left out as SARIF reports are usually uploaded.
+fn rule(result: &ScanResult) -> Value {
+    let mut rule = json!({
+        "id": result.name,
+        "shortDescription": {
+            "text": result.description.clone().unwrap_or_else(|| result.name.clone()),
+        },
+        "properties": {
+            "severity": result.severity.to_string(),
+            "system": result.system,
+            "tags": result.tags,
+        },
+    });
+
+    if let Some(remediation) = &result.remediation {
+        rule["help"] = json!({ "text": remediation });
+    }
+
+    rule
+}
+
+fn result(result: &ScanResult) -> Value {
+    let mut sarif = json!({
+        "ruleId": result.name,
+        "level": level(result),
+        "message": {
+            "text": format!("{} was found by system {}", result.name, result.system),
+        },
+    });
+
+    // A plain SHA-256 of a short secret can be reversed by brute force so
+    // only keyed fingerprints are published
+    if result.keyed_hash {
+        sarif["partialFingerprints"] = json!({ "himitsu/valueHash": result.value_hash });
+    }
+
+    if let (Some(path), Some(line), Some(column)) = (&result.path, result.line, result.column) {
+        sarif["locations"] = json!([{
+            "physicalLocation": {
+                "artifactLocation": { "uri": path },
+                "region": { "startLine": line, "startColumn": column },
+            },
+        }]);
+    }
+
+    if let Some(commit) = &result.commit {
+        sarif["properties"] = json!({ "commit": commit });
+    }
+
+    match result.state {
+        FindingState::Suppressed => sarif["suppressions"] = json!([{ "kind": "inSource" }]),
+        FindingState::Removed => sarif["kind"] = json!("informational"),
+        FindingState::Active | FindingState::Warning => {}
+    }
+
+    sarif
+}
+
+pub fn render(results: &ScanResults) -> String {
+    let results = sorted(results);
+    let rules: BTreeMap<&str, Value> = results.iter().map(|r| (r.name.as_str(), rule(r))).collect();
+
+    let report = json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "Himitsu",
+                    "rules": rules.into_values().collect::<Vec<_>>(),
+                },
+            },
+            "results": results.into_iter().map(result).collect::<Vec<_>>(),
+        }],
+    });
+
+    serde_json::to_string_pretty(&report).unwrap()
+}
@@ -0,0 +1,118 @@
+use serde_derive::Deserialize;
+
+use crate::{message::HimitsuResponse, FindingState, ScanResults, Severity};
+
+/// Decides what happens to a change based on the severity of the secrets
+/// found in it. Findings at or above `block` block the change, findings at
+/// or above `warn` are shown to the developer without blocking, and anything
+/// lower is only logged.
+#[derive(Deserialize)]
+pub struct Policy {
+    #[serde(default = "default_threshold")]
+    pub block: Severity,
+    #[serde(default = "default_threshold")]
+    pub warn: Severity,
+}
+
+// By default every finding blocks, which is how Himitsu behaved before
+// policies existed.
+fn default_threshold() -> Severity {
+    Severity::Info
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            block: default_threshold(),
+            warn: default_threshold(),
+        }
+    }
+}
+
+impl Policy {
+    /// Turn the results of a scan into the response that should be sent to
+    /// the client. Active findings below the blocking threshold are marked as
+    /// warnings and ones below the warning threshold are logged and dropped.
+    pub fn evaluate(&self, results: ScanResults) -> HimitsuResponse {
+        let mut blocking = false;
+        let mut warning = false;
+
+        let results: ScanResults = results
+            .into_iter()
+            .filter_map(|mut result| {
+                if !result.is_active() {
+                    return Some(result);
+                }
+
+                if result.severity >= self.block {
+                    blocking = true;
+                } else if result.severity >= self.warn {
+                    result.state = FindingState::Warning;
+                    warning = true;
+                } else {
+                    info!(
+                        "Not reporting {} finding {} below the warning threshold",
+                        result.severity, result.name
+                    );
+                    return None;
+                }
+
+                Some(result)
+            })
+            .collect();
+
+        if blocking {
+            HimitsuResponse::SecretsFound(results)
+        } else if warning {
+            HimitsuResponse::Warnings(results)
+        } else if !results.is_empty() {
+            HimitsuResponse::Informational(results)
+        } else {
+            HimitsuResponse::Clean
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scanners::Location, ScanResult};
+
+    fn finding(name: &str, severity: Severity) -> ScanResult {
+        let mut result = ScanResult::new("Regex", name, name, Location::default());
+        result.severity = severity;
+        result
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy {
+            block: Severity::High,
+            warn: Severity::Medium,
+        };
+
+        let response = policy.evaluate(ScanResults::from([
+            finding("GenericSecret", Severity::Medium),
+            finding("SlackInternal", Severity::Low),
+        ]));
+        match response {
+            HimitsuResponse::Warnings(results) => {
+                assert_eq!(results.len(), 1);
+                let result = results.iter().next().unwrap();
+                assert_eq!(result.name, "GenericSecret");
+                assert_eq!(result.state, FindingState::Warning);
+            }
+            r => panic!("Expected warnings, got {:?}", r),
+        }
+
+        let response = policy.evaluate(ScanResults::from([
+            finding("GenericSecret", Severity::Medium),
+            finding("AwsKey", Severity::Critical),
+        ]));
+        assert!(matches!(response, HimitsuResponse::SecretsFound(r) if r.len() == 2));
+
+        let response =
+            policy.evaluate(ScanResults::from([finding("SlackInternal", Severity::Low)]));
+        assert!(matches!(response, HimitsuResponse::Clean));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    envelope::Metadata,
+    error::{HResult, HimitsuError},
+    storage,
+};
+
+/// Where accepted configuration versions are kept,
+/// `$XDG_STATE_HOME/himitsu/state.json` falling back to `~/.local/state`
+pub fn default_path() -> Option<PathBuf> {
+    Some(storage::xdg_dir("XDG_STATE_HOME", ".local/state")?.join("state.json"))
+}
+
+/// The highest version accepted for each configuration name. It is persisted
+/// so an older configuration can't be served once a newer one was seen, even
+/// across restarts.
+#[derive(Serialize, Deserialize, Default)]
+pub struct AcceptedVersions {
+    versions: HashMap<String, u64>,
+}
+
+impl AcceptedVersions {
+    pub fn load(path: &Path) -> HResult<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, path: &Path) -> HResult<()> {
+        Ok(storage::write_atomically(path, &serde_json::to_vec(self)?)?)
+    }
+
+    /// Reject configurations older than the last accepted one. The same
+    /// version is accepted again so refreshing an unchanged config works.
+    pub fn check(&self, metadata: &Metadata) -> HResult<()> {
+        match self.versions.get(&metadata.name) {
+            Some(&accepted) if metadata.version < accepted => Err(HimitsuError::RollbackError(
+                metadata.name.clone(),
+                metadata.version,
+                accepted,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a configuration against the versions stored at `path` and
+    /// remember it as the latest accepted version. Once any version was
+    /// accepted, configurations without one are refused so leaving the
+    /// metadata out can't get around the check.
+    pub fn accept(path: &Path, metadata: Option<&Metadata>) -> HResult<()> {
+        let mut accepted = Self::load(path)?;
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None if accepted.versions.is_empty() => return Ok(()),
+            None => {
+                return Err(HimitsuError::CryptographyError(
+                    "Configuration has no signed version but versioned configurations were \
+                     accepted before"
+                        .to_string(),
+                ))
+            }
+        };
+        accepted.check(metadata)?;
+
+        info!(
+            "Accepted configuration {} version {} issued at {}",
+            metadata.name,
+            metadata.version,
+            humantime::format_rfc3339(UNIX_EPOCH + Duration::from_secs(metadata.issued_at))
+        );
+        if accepted.versions.get(&metadata.name) == Some(&metadata.version) {
+            return Ok(());
+        }
+        accepted
+            .versions
+            .insert(metadata.name.clone(), metadata.version);
+
+        // Failing to save only weakens the protection after a restart, which
+        // isn't worth refusing an otherwise valid configuration over
+        if let Err(e) = accepted.save(path) {
+            error!(
+                "Could not save accepted configuration versions to {}: {e}",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_rollback() {
+        let path = env::temp_dir().join(format!("himitsu-rollback-{}.json", std::process::id()));
+        let metadata = |name: &str, version| Metadata {
+            name: name.to_string(),
+            version,
+            issued_at: 0,
+        };
+
+        assert!(AcceptedVersions::accept(&path, None).is_ok());
+        assert!(AcceptedVersions::accept(&path, Some(&metadata("production", 2))).is_ok());
+        assert!(AcceptedVersions::accept(&path, Some(&metadata("production", 2))).is_ok());
+        // Leaving the version out no longer gets a configuration accepted
+        assert!(AcceptedVersions::accept(&path, None).is_err());
+        assert!(matches!(
+            AcceptedVersions::accept(&path, Some(&metadata("production", 1))),
+            Err(HimitsuError::RollbackError(_, 1, 2))
+        ));
+        // Versions are tracked per configuration
+        assert!(AcceptedVersions::accept(&path, Some(&metadata("staging", 1))).is_ok());
+        assert!(AcceptedVersions::accept(&path, Some(&metadata("production", 3))).is_ok());
+        assert!(AcceptedVersions::load(&path)
+            .unwrap()
+            .check(&metadata("production", 2))
+            .is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
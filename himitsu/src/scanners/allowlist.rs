@@ -0,0 +1,409 @@
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+use serde::{de::Error, Deserialize, Deserializer};
+use serde_derive::Deserialize;
+
+use super::{fingerprint, ScanResult};
+
+/// Findings matching any part of the allowlist are not reported
+#[derive(Deserialize, Default)]
+pub struct Allowlist {
+    /// Hashes of values that are never reported, whichever rule finds them
+    #[serde(default)]
+    hashes: Vec<AllowedHash>,
+    /// Globs of paths that are not scanned at all, such as `**/testdata/**`
+    /// or `*.lock`
+    #[serde(default, deserialize_with = "deserialize_globs")]
+    paths: Vec<Regex>,
+    /// Regexes matched against found values, for things like `EXAMPLE`
+    /// placeholders in documentation
+    #[serde(default, deserialize_with = "deserialize_regexes")]
+    values: Vec<Regex>,
+    /// Allowlist entries that only apply to a single rule
+    #[serde(default)]
+    rules: Vec<RuleAllowlist>,
+}
+
+/// Allows findings from one rule. Every condition that is given must match,
+/// so an entry with only `paths` disables the rule for those paths while an
+/// entry with only `hashes` allows those values for that rule anywhere.
+#[derive(Deserialize)]
+pub struct RuleAllowlist {
+    rule: String,
+    #[serde(default, deserialize_with = "deserialize_globs")]
+    paths: Vec<Regex>,
+    #[serde(default)]
+    hashes: Vec<AllowedHash>,
+    #[serde(default, deserialize_with = "deserialize_regexes")]
+    values: Vec<Regex>,
+}
+
+/// A hash on the allowlist. Entries can be plain hashes or objects that
+/// record why the exception exists and when it stops applying.
+#[derive(Deserialize)]
+#[serde(try_from = "AllowedHashDefinition")]
+pub struct AllowedHash {
+    hash: String,
+    reason: Option<String>,
+    owner: Option<String>,
+    /// After this time the entry is ignored
+    expires: Option<SystemTime>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AllowedHashDefinition {
+    Hash(String),
+    Entry {
+        hash: String,
+        #[serde(default)]
+        reason: Option<String>,
+        #[serde(default)]
+        owner: Option<String>,
+        /// An RFC 3339 timestamp in UTC, or just a date in which case the
+        /// entry still applies for all of that day (UTC)
+        #[serde(default)]
+        expires: Option<String>,
+    },
+}
+
+impl TryFrom<AllowedHashDefinition> for AllowedHash {
+    type Error = String;
+
+    fn try_from(definition: AllowedHashDefinition) -> Result<Self, Self::Error> {
+        match definition {
+            AllowedHashDefinition::Hash(hash) => Ok(Self {
+                hash,
+                reason: None,
+                owner: None,
+                expires: None,
+            }),
+            AllowedHashDefinition::Entry {
+                hash,
+                reason,
+                owner,
+                expires,
+            } => {
+                let expires = expires
+                    .map(|expires| {
+                        let (timestamp, extra) = if expires.contains('T') {
+                            (expires.clone(), Duration::ZERO)
+                        } else {
+                            (
+                                format!("{expires}T00:00:00Z"),
+                                Duration::from_secs(24 * 60 * 60),
+                            )
+                        };
+                        humantime::parse_rfc3339(&timestamp)
+                            .map(|start| start + extra)
+                            .map_err(|e| {
+                                format!("Invalid expiry {expires} for allowlist entry {hash}: {e}")
+                            })
+                    })
+                    .transpose()?;
+
+                Ok(Self {
+                    hash,
+                    reason,
+                    owner,
+                    expires,
+                })
+            }
+        }
+    }
+}
+
+impl AllowedHash {
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires <= SystemTime::now())
+    }
+
+    /// Entries prefixed with `sha256:` are always compared against the plain
+    /// SHA-256 of the value so allowlists keep working after switching to a
+    /// keyed fingerprint. Other entries are compared against the configured
+    /// fingerprint.
+    fn matches(&self, result: &ScanResult) -> bool {
+        let matches = match self.hash.strip_prefix("sha256:") {
+            Some(hash) => hash == fingerprint::sha256(&result.value),
+            None => self.hash == result.value_hash,
+        };
+
+        matches && !self.is_expired()
+    }
+
+    /// Describe an expired entry for the config lint output
+    fn expired_message(&self, rule: Option<&str>) -> Option<String> {
+        if !self.is_expired() {
+            return None;
+        }
+
+        let mut message = format!("Allowlist entry {} ", self.hash);
+        if let Some(rule) = rule {
+            message.push_str(&format!("for rule {rule} "));
+        }
+        if let Some(expires) = self.expires {
+            message.push_str(&format!(
+                "expired at {}",
+                humantime::format_rfc3339(expires)
+            ));
+        }
+        if let Some(owner) = &self.owner {
+            message.push_str(&format!(", owner: {owner}"));
+        }
+        if let Some(reason) = &self.reason {
+            message.push_str(&format!(", reason: {reason}"));
+        }
+
+        Some(message)
+    }
+}
+
+fn contains_hash(hashes: &[AllowedHash], result: &ScanResult) -> bool {
+    hashes.iter().any(|allowed| allowed.matches(result))
+}
+
+/// Older configurations only support a list of hashes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AllowlistDefinition {
+    Hashes(Vec<AllowedHash>),
+    Allowlist(Allowlist),
+}
+
+pub fn deserialize_allowlist<'de, D>(deserializer: D) -> Result<Allowlist, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(
+        match Option::<AllowlistDefinition>::deserialize(deserializer)? {
+            Some(AllowlistDefinition::Hashes(hashes)) => Allowlist {
+                hashes,
+                ..Allowlist::default()
+            },
+            Some(AllowlistDefinition::Allowlist(allowlist)) => allowlist,
+            None => Allowlist::default(),
+        },
+    )
+}
+
+fn deserialize_regexes<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|r| Regex::new(r).map_err(|e| D::Error::custom(e.to_string())))
+        .collect()
+}
+
+fn deserialize_globs<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|g| glob_to_regex(g).map_err(|e| D::Error::custom(e.to_string())))
+        .collect()
+}
+
+/// Convert a glob into an anchored regex. `**` matches across directories,
+/// `*` and `?` match within a single path component, and globs without a
+/// `/` match the file name in any directory like they do in `.gitignore`.
+pub fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    if !glob.contains('/') {
+        pattern.push_str("(?:.*/)?");
+    }
+
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    pattern.push_str("(?:.*/)?");
+                } else {
+                    pattern.push_str(".*");
+                }
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern)
+}
+
+fn path_matches(globs: &[Regex], path: Option<&str>) -> bool {
+    path.is_some_and(|path| globs.iter().any(|glob| glob.is_match(path)))
+}
+
+impl RuleAllowlist {
+    fn allows(&self, result: &ScanResult) -> bool {
+        self.rule == result.name
+            && (self.paths.is_empty() || path_matches(&self.paths, result.path.as_deref()))
+            && (self.hashes.is_empty() || contains_hash(&self.hashes, result))
+            && (self.values.is_empty() || self.values.iter().any(|v| v.is_match(&result.value)))
+    }
+}
+
+impl Allowlist {
+    /// Whether a file should be skipped entirely
+    pub fn excludes_path(&self, path: Option<&str>) -> bool {
+        path_matches(&self.paths, path)
+    }
+
+    /// Whether a finding is covered by the allowlist and should be dropped
+    pub fn allows(&self, result: &ScanResult) -> bool {
+        contains_hash(&self.hashes, result)
+            || self.excludes_path(result.path.as_deref())
+            || self.values.iter().any(|v| v.is_match(&result.value))
+            || self.rules.iter().any(|rule| rule.allows(result))
+    }
+
+    /// Problems with the allowlist that should be fixed, such as entries
+    /// that have expired and are now being ignored
+    pub fn lint(&self) -> Vec<String> {
+        let global = self
+            .hashes
+            .iter()
+            .filter_map(|hash| hash.expired_message(None));
+        let rules = self.rules.iter().flat_map(|rule| {
+            rule.hashes
+                .iter()
+                .filter_map(|hash| hash.expired_message(Some(&rule.rule)))
+        });
+
+        global.chain(rules).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanners::Location;
+
+    fn finding(name: &str, value: &str, path: &str) -> ScanResult {
+        ScanResult::new(
+            "Regex",
+            name,
+            value,
+            Location {
+                path: Some(path.to_string()),
+                line: 1,
+                column: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let glob = glob_to_regex("**/testdata/**").unwrap();
+        assert!(glob.is_match("testdata/key.pem"));
+        assert!(glob.is_match("src/module/testdata/nested/key.pem"));
+        assert!(!glob.is_match("src/testdata.rs"));
+
+        let glob = glob_to_regex("*.lock").unwrap();
+        assert!(glob.is_match("Cargo.lock"));
+        assert!(glob.is_match("web/yarn.lock"));
+        assert!(!glob.is_match("Cargo.lock.bak"));
+
+        let glob = glob_to_regex("docs/*.md").unwrap();
+        assert!(glob.is_match("docs/setup.md"));
+        assert!(!glob.is_match("docs/internal/setup.md"));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist: Allowlist = serde_json::from_str(
+            r#"{
+                "paths": ["**/testdata/**"],
+                "values": ["EXAMPLE"],
+                "rules": [
+                    {"rule": "GenericSecret", "paths": ["docs/**"]},
+                    {"rule": "AwsKey", "hashes": ["abc"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(allowlist.allows(&finding("AwsKey", "AKIA1", "a/testdata/b.txt")));
+        assert!(allowlist.allows(&finding("AwsKey", "AKIAEXAMPLE", "src/main.rs")));
+        assert!(allowlist.allows(&finding("GenericSecret", "hunter2", "docs/setup.md")));
+        assert!(!allowlist.allows(&finding("GenericSecret", "hunter2", "src/main.rs")));
+        assert!(!allowlist.allows(&finding("AwsKey", "AKIA1", "docs/setup.md")));
+
+        let mut result = finding("AwsKey", "AKIA1", "src/main.rs");
+        result.value_hash = "abc".to_string();
+        assert!(allowlist.lint().is_empty());
+        assert!(allowlist.allows(&result));
+        result.name = "NpmToken".to_string();
+        assert!(!allowlist.allows(&result));
+    }
+
+    #[test]
+    fn test_legacy_sha256_entries() {
+        let allowlist: Allowlist = serde_json::from_str(&format!(
+            r#"{{"hashes": ["sha256:{}"]}}"#,
+            fingerprint::sha256("AKIA1")
+        ))
+        .unwrap();
+
+        // The value hash is a keyed fingerprint but the entry still matches
+        let mut result = finding("AwsKey", "AKIA1", "src/main.rs");
+        result.value_hash = "0123456789abcdef".to_string();
+        assert!(allowlist.allows(&result));
+
+        result.value = "AKIA2".to_string();
+        assert!(!allowlist.allows(&result));
+    }
+
+    #[test]
+    fn test_expiring_entries() {
+        let allowlist: Allowlist = serde_json::from_str(
+            r#"{
+                "hashes": [
+                    "plain",
+                    {"hash": "current", "owner": "security", "expires": "2999-01-01"},
+                    {
+                        "hash": "abc123",
+                        "reason": "Rotating next sprint",
+                        "owner": "security",
+                        "expires": "2020-01-01T12:00:00Z"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mut result = finding("AwsKey", "AKIA1", "src/main.rs");
+        for (hash, allowed) in [("plain", true), ("current", true), ("abc123", false)] {
+            result.value_hash = hash.to_string();
+            assert_eq!(allowlist.allows(&result), allowed);
+        }
+
+        assert_eq!(
+            allowlist.lint(),
+            vec![
+                "Allowlist entry abc123 expired at 2020-01-01T12:00:00Z, owner: security, reason: Rotating next sprint"
+            ]
+        );
+
+        // A date on its own lasts until the end of that day
+        let entry: AllowedHash =
+            serde_json::from_str(r#"{"hash": "a", "expires": "2020-01-01"}"#).unwrap();
+        assert_eq!(
+            entry.expires,
+            Some(humantime::parse_rfc3339("2020-01-02T00:00:00Z").unwrap())
+        );
+
+        let invalid: Result<Allowlist, _> =
+            serde_json::from_str(r#"{"hashes": [{"hash": "a", "expires": "soon"}]}"#);
+        assert!(invalid.is_err());
+    }
+}
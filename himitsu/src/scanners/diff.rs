@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+
+/// Where in the scanned input a finding was located
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    pub path: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A contiguous piece of text handed to a scanning system along with
+/// enough information to map byte offsets back to file and line.
+pub struct ScanTarget {
+    pub path: Option<String>,
+    pub text: String,
+    /// Pairs of (byte offset into `text`, line number in the source file)
+    /// for the start of each line in `text`.
+    lines: Vec<(usize, usize)>,
+    /// Lines of the same file that are not scanned but are still useful
+    /// context, such as unchanged lines around an addition.
+    surrounding: HashMap<usize, String>,
+}
+
+impl ScanTarget {
+    pub fn new(path: Option<String>) -> Self {
+        Self {
+            path,
+            text: String::new(),
+            lines: Vec::new(),
+            surrounding: HashMap::new(),
+        }
+    }
+
+    /// Create a target from plain text where line numbers start at 1
+    pub fn from_text(path: Option<String>, text: &str) -> Self {
+        let mut target = Self::new(path);
+        for (i, line) in text.lines().enumerate() {
+            target.push_line(i + 1, line);
+        }
+        target
+    }
+
+    /// Create a target from plain text that only scans the given line
+    /// numbers, keeping the other lines as context
+    pub fn from_added_lines(path: Option<String>, text: &str, added: &[usize]) -> Self {
+        let added: HashSet<&usize> = added.iter().collect();
+        let mut target = Self::new(path);
+        for (i, line) in text.lines().enumerate() {
+            match added.contains(&(i + 1)) {
+                true => target.push_line(i + 1, line),
+                false => target.push_surrounding_line(i + 1, line),
+            }
+        }
+        target
+    }
+
+    pub fn push_line(&mut self, number: usize, content: &str) {
+        self.lines.push((self.text.len(), number));
+        self.text.push_str(content);
+        self.text.push('\n');
+    }
+
+    /// Record a line of the file that is not part of the scanned text
+    pub fn push_surrounding_line(&mut self, number: usize, content: &str) {
+        self.surrounding.insert(number, content.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Get the contents of a line by its line number in the source file if
+    /// it is known to this target.
+    pub fn line(&self, number: usize) -> Option<&str> {
+        match self.lines.iter().position(|(_, n)| *n == number) {
+            Some(i) => {
+                let start = self.lines[i].0;
+                let end = self
+                    .lines
+                    .get(i + 1)
+                    .map(|(offset, _)| *offset)
+                    .unwrap_or(self.text.len());
+                Some(self.text[start..end].trim_end_matches('\n'))
+            }
+            None => self.surrounding.get(&number).map(|l| l.as_str()),
+        }
+    }
+
+    /// Map a byte offset in `text` to a file location. Columns are
+    /// 1-based and counted in characters.
+    pub fn locate(&self, offset: usize) -> Location {
+        let index = match self
+            .lines
+            .binary_search_by_key(&offset, |(start, _)| *start)
+        {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+
+        let (start, line) = self.lines.get(index).copied().unwrap_or((0, 0));
+        let column = self.text[start..offset].chars().count() + 1;
+
+        Location {
+            path: self.path.clone(),
+            line,
+            column,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Added,
+    Removed,
+    Context,
+}
+
+#[derive(Debug)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// The line number on the new side for added and context lines, and
+    /// on the old side for removed lines
+    pub number: usize,
+    pub content: String,
+}
+
+#[derive(Debug)]
+pub struct DiffFile {
+    pub path: String,
+    pub lines: Vec<DiffLine>,
+}
+
+/// Strip the `a/` or `b/` prefix git puts on paths in diff headers as well
+/// as any trailing timestamp some diff tools append after a tab.
+fn clean_path(path: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or(path).trim();
+    let path = path.trim_matches('"');
+
+    if path == "/dev/null" {
+        return None;
+    }
+
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+
+    Some(path.to_string())
+}
+
+/// Parse the `-a,b +c,d` ranges out of a hunk header, returning the
+/// starting line and line count for the old and new sides.
+fn parse_hunk_header(line: &str) -> Option<((usize, usize), (usize, usize))> {
+    let ranges = line.strip_prefix("@@ ")?;
+    let ranges = &ranges[..ranges.find(" @@")?];
+    let (old, new) = ranges.split_once(' ')?;
+
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+
+    Some((
+        parse_range(old.strip_prefix('-')?)?,
+        parse_range(new.strip_prefix('+')?)?,
+    ))
+}
+
+/// Parse unified diff output (as produced by `git diff`) into the files it
+/// touches. Returns an empty list if the input contains no diff headers.
+pub fn parse_diff(diff: &str) -> Vec<DiffFile> {
+    let mut files: Vec<DiffFile> = Vec::new();
+    // Lines left to read in the current hunk on the old and new sides
+    let mut remaining: (usize, usize) = (0, 0);
+    let mut line_numbers = (0, 0);
+    let mut old_path = None;
+
+    for line in diff.lines() {
+        if remaining.0 > 0 || remaining.1 > 0 {
+            let file = match files.last_mut() {
+                Some(file) => file,
+                None => break,
+            };
+
+            let (kind, content) = match line.chars().next() {
+                Some('+') => (LineKind::Added, &line[1..]),
+                Some('-') => (LineKind::Removed, &line[1..]),
+                Some(' ') => (LineKind::Context, &line[1..]),
+                // Empty context lines sometimes lose their leading space
+                None => (LineKind::Context, ""),
+                // "\ No newline at end of file"
+                Some('\\') => continue,
+                _ => {
+                    // Malformed hunk, stop treating lines as part of it
+                    remaining = (0, 0);
+                    continue;
+                }
+            };
+
+            let number = match kind {
+                LineKind::Added => {
+                    line_numbers.1 += 1;
+                    remaining.1 = remaining.1.saturating_sub(1);
+                    line_numbers.1 - 1
+                }
+                LineKind::Removed => {
+                    line_numbers.0 += 1;
+                    remaining.0 = remaining.0.saturating_sub(1);
+                    line_numbers.0 - 1
+                }
+                LineKind::Context => {
+                    line_numbers.0 += 1;
+                    line_numbers.1 += 1;
+                    remaining.0 = remaining.0.saturating_sub(1);
+                    remaining.1 = remaining.1.saturating_sub(1);
+                    line_numbers.1 - 1
+                }
+            };
+
+            file.lines.push(DiffLine {
+                kind,
+                number,
+                content: content.to_string(),
+            });
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix("diff --git ") {
+            // The real paths come from the ---/+++ lines but those are missing
+            // for pure renames and mode changes so fall back to the b/ side here.
+            let path = header
+                .rsplit_once(" b/")
+                .map(|(_, path)| path.to_string())
+                .unwrap_or_else(|| header.to_string());
+            files.push(DiffFile {
+                path,
+                lines: Vec::new(),
+            });
+            old_path = None;
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            old_path = clean_path(path);
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            // Deleted files have /dev/null as their new path
+            let path = clean_path(path).or_else(|| old_path.clone());
+            match (files.last_mut(), path) {
+                (Some(file), Some(path)) if file.lines.is_empty() => file.path = path,
+                // A plain unified diff without the `diff --git` line
+                (_, Some(path)) => files.push(DiffFile {
+                    path,
+                    lines: Vec::new(),
+                }),
+                _ => (),
+            }
+        } else if let Some(((old_start, old_count), (new_start, new_count))) =
+            parse_hunk_header(line)
+        {
+            remaining = (old_count, new_count);
+            line_numbers = (old_start, new_start);
+        }
+    }
+
+    files
+}
+
+/// Split the scanner input into targets made up of only the lines of the
+/// given kind. Diffs produce one target per file while anything else is
+/// treated as a single target of added lines without a path.
+pub fn build_targets(data: &str, kind: LineKind) -> Vec<ScanTarget> {
+    let files = parse_diff(data);
+
+    if files.is_empty() {
+        return match kind {
+            LineKind::Added => vec![ScanTarget::from_text(None, data)],
+            _ => Vec::new(),
+        };
+    }
+
+    files
+        .into_iter()
+        .map(|file| {
+            let mut target = ScanTarget::new(Some(file.path));
+            for line in file.lines.iter() {
+                if line.kind == kind {
+                    target.push_line(line.number, &line.content);
+                } else if kind == LineKind::Added && line.kind == LineKind::Context {
+                    target.push_surrounding_line(line.number, &line.content);
+                }
+            }
+            target
+        })
+        .filter(|target| !target.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_DIFF: &str = "\
+diff --git a/src/main.rs b/src/main.rs
+index 1111111..2222222 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -10,3 +10,4 @@ fn main() {
+     let a = 1;
+-    let b = 2;
++    let b = 3;
++    let c = 4;
+     let d = 5;
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+";
+
+    #[test]
+    fn test_parse_diff() {
+        let files = parse_diff(EXAMPLE_DIFF);
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].path, "src/main.rs");
+        let lines: Vec<_> = files[0]
+            .lines
+            .iter()
+            .map(|l| (l.kind, l.number, l.content.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (LineKind::Context, 10, "    let a = 1;"),
+                (LineKind::Removed, 11, "    let b = 2;"),
+                (LineKind::Added, 11, "    let b = 3;"),
+                (LineKind::Added, 12, "    let c = 4;"),
+                (LineKind::Context, 13, "    let d = 5;"),
+            ]
+        );
+
+        assert_eq!(files[1].path, "old.txt");
+        assert_eq!(files[1].lines[0].kind, LineKind::Removed);
+        assert_eq!(files[1].lines[0].number, 1);
+    }
+
+    #[test]
+    fn test_build_targets() {
+        let targets = build_targets(EXAMPLE_DIFF, LineKind::Added);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].text, "    let b = 3;\n    let c = 4;\n");
+
+        let target = &targets[0];
+        let offset = target.text.find("c = 4").unwrap();
+
+        assert_eq!(
+            target.locate(offset),
+            Location {
+                path: Some("src/main.rs".to_string()),
+                line: 12,
+                column: 9,
+            }
+        );
+
+        let targets = build_targets(EXAMPLE_DIFF, LineKind::Removed);
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[1].path.as_deref(), Some("old.txt"));
+        assert_eq!(targets[1].text, "gone\n");
+    }
+}
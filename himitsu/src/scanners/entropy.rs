@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde_derive::Deserialize;
+
+use crate::ScanResults;
+
+use super::{RuleMetadata, ScanResult, ScanTarget};
+
+/// When a token is considered high entropy for a given character set
+#[derive(Deserialize, Clone, Copy)]
+pub struct EntropyThreshold {
+    /// Minimum Shannon entropy in bits per character
+    pub threshold: f64,
+    /// Tokens shorter than this are never reported
+    pub min_length: usize,
+}
+
+#[derive(Deserialize)]
+pub struct EntropySystem {
+    /// Settings for strings made up of base64 (or base64url) characters.
+    /// Set to null to disable.
+    #[serde(default = "default_base64")]
+    base64: Option<EntropyThreshold>,
+    /// Settings for strings made up only of hex characters.
+    /// Set to null to disable.
+    #[serde(default = "default_hex")]
+    hex: Option<EntropyThreshold>,
+    /// Description, severity and remediation applied to all entropy findings
+    #[serde(flatten)]
+    metadata: RuleMetadata,
+    #[serde(skip, default = "token_regex")]
+    tokens: Regex,
+}
+
+fn default_base64() -> Option<EntropyThreshold> {
+    Some(EntropyThreshold {
+        threshold: 4.5,
+        min_length: 20,
+    })
+}
+
+fn default_hex() -> Option<EntropyThreshold> {
+    Some(EntropyThreshold {
+        threshold: 3.0,
+        min_length: 32,
+    })
+}
+
+fn token_regex() -> Regex {
+    Regex::new(r"[A-Za-z0-9+/_\-]+={0,2}").unwrap()
+}
+
+/// Calculate the Shannon entropy of a string in bits per character
+pub fn shannon_entropy(data: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    let mut len = 0;
+    for c in data.chars() {
+        *counts.entry(c).or_default() += 1;
+        len += 1;
+    }
+
+    if len == 0 {
+        return 0.0;
+    }
+
+    counts
+        .values()
+        .map(|count| {
+            let p = *count as f64 / len as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+impl super::System for EntropySystem {
+    fn scan(&self, target: &ScanTarget) -> ScanResults {
+        self.tokens
+            .find_iter(&target.text)
+            .filter_map(|token| {
+                let value = token.as_str();
+                let (name, settings) = if value.chars().all(|c| c.is_ascii_hexdigit()) {
+                    ("HighEntropyHex", self.hex?)
+                } else {
+                    ("HighEntropyBase64", self.base64?)
+                };
+
+                if value.len() < settings.min_length
+                    || shannon_entropy(value.trim_end_matches('=')) < settings.threshold
+                {
+                    return None;
+                }
+
+                Some(
+                    ScanResult::new("Entropy", name, value, target.locate(token.start()))
+                        .with_metadata(&self.metadata),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::System;
+    use super::*;
+
+    #[test]
+    fn test_entropy_scan() {
+        let system: EntropySystem = serde_json::from_str("{}").unwrap();
+        let target = ScanTarget::from_text(
+            None,
+            concat!(
+                "let name = \"this_is_a_very_long_but_boring_identifier\";\n",
+                "let token = \"q8Zr4LkP2vXw9TnB7mYs3HdF6gJc1Qa\";\n",
+                "let digest = \"9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08\";\n",
+            ),
+        );
+
+        let mut results: Vec<_> = system.scan(&target).into_iter().collect();
+        results.sort_by_key(|r| r.line);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "HighEntropyBase64");
+        assert_eq!(results[0].value, "q8Zr4LkP2vXw9TnB7mYs3HdF6gJc1Qa");
+        assert_eq!(results[0].line, Some(2));
+        assert_eq!(results[1].name, "HighEntropyHex");
+        assert_eq!(results[1].line, Some(3));
+    }
+}
@@ -0,0 +1,101 @@
+use ring::{
+    digest::{digest, SHA256},
+    hmac,
+};
+use serde_derive::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FingerprintAlgorithm {
+    /// Plain SHA-256 of the value. Cheap to brute force for short secrets.
+    #[default]
+    Sha256,
+    /// HMAC-SHA256 of the value keyed with an organization secret
+    HmacSha256,
+}
+
+/// Computes the `value_hash` of findings. The configuration declares which
+/// algorithm its allowlist hashes were made with.
+#[derive(Deserialize, Default)]
+#[serde(try_from = "FingerprintDefinition")]
+pub struct Fingerprinter {
+    algorithm: FingerprintAlgorithm,
+    key: Option<hmac::Key>,
+}
+
+#[derive(Deserialize)]
+struct FingerprintDefinition {
+    #[serde(default)]
+    algorithm: FingerprintAlgorithm,
+    /// Hex encoded HMAC key, required for `hmac-sha256`
+    #[serde(default)]
+    key: Option<String>,
+}
+
+impl TryFrom<FingerprintDefinition> for Fingerprinter {
+    type Error = String;
+
+    fn try_from(definition: FingerprintDefinition) -> Result<Self, Self::Error> {
+        let key = match (definition.algorithm, definition.key) {
+            (FingerprintAlgorithm::Sha256, _) => None,
+            (FingerprintAlgorithm::HmacSha256, Some(key)) => {
+                let key = hex::decode(key).map_err(|_| "Invalid fingerprint key".to_string())?;
+                Some(hmac::Key::new(hmac::HMAC_SHA256, &key))
+            }
+            (FingerprintAlgorithm::HmacSha256, None) => {
+                return Err("The hmac-sha256 fingerprint algorithm requires a key".to_string())
+            }
+        };
+
+        Ok(Self {
+            algorithm: definition.algorithm,
+            key,
+        })
+    }
+}
+
+/// The legacy fingerprint of a value, hex encoded SHA-256
+pub fn sha256(value: &str) -> String {
+    hex::encode(digest(&SHA256, value.as_bytes()))
+}
+
+impl Fingerprinter {
+    pub fn is_keyed(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn fingerprint(&self, value: &str) -> String {
+        match (&self.algorithm, &self.key) {
+            (FingerprintAlgorithm::HmacSha256, Some(key)) => {
+                hex::encode(hmac::sign(key, value.as_bytes()))
+            }
+            _ => sha256(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprinter = Fingerprinter::default();
+        assert_eq!(
+            fingerprinter.fingerprint("secret"),
+            "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+        );
+
+        // The key is "key" hex encoded
+        let fingerprinter: Fingerprinter =
+            serde_json::from_str(r#"{"algorithm": "hmac-sha256", "key": "6b6579"}"#).unwrap();
+        assert_eq!(
+            fingerprinter.fingerprint("The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+
+        let missing_key: Result<Fingerprinter, _> =
+            serde_json::from_str(r#"{"algorithm": "hmac-sha256"}"#);
+        assert!(missing_key.is_err());
+    }
+}
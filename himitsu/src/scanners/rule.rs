@@ -0,0 +1,57 @@
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// How serious a finding from a rule is
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, Default, Eq, PartialEq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    /// Rules that don't declare a severity are treated as high so they
+    /// keep blocking like they did before severities existed
+    #[default]
+    High,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Optional information about a rule that is attached to its findings
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RuleMetadata {
+    /// What the rule detects
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub severity: Severity,
+    /// How to rotate or otherwise deal with a leaked secret, either as text
+    /// or a URL to a runbook
+    #[serde(default)]
+    pub remediation: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl RuleMetadata {
+    pub fn with_severity(severity: Severity) -> Self {
+        Self {
+            severity,
+            ..Self::default()
+        }
+    }
+}
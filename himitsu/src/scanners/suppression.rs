@@ -0,0 +1,62 @@
+use super::{ScanResult, ScanTarget};
+
+/// Marker developers can leave in a comment on the same line as a finding,
+/// or on the line before it, to suppress that finding.
+pub const SUPPRESSION_MARKER: &str = "himitsu:allow";
+
+/// Check whether a line suppresses findings from the given rule. A bare
+/// marker suppresses every rule while `himitsu:allow AwsKey,GenericSecret`
+/// only suppresses the rules listed.
+pub fn line_suppresses(line: &str, rule: &str) -> bool {
+    line.match_indices(SUPPRESSION_MARKER).any(|(i, _)| {
+        let rest = &line[i + SUPPRESSION_MARKER.len()..];
+
+        // Don't match things like `himitsu:allowed`
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return false;
+        }
+
+        let rules: Vec<&str> = rest
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|r| r.chars().next().is_some_and(|c| c.is_alphanumeric()))
+            .collect();
+
+        rules.is_empty() || rules.contains(&rule)
+    })
+}
+
+/// Check the line of the finding and the line before it for a suppression
+/// marker covering the finding's rule.
+pub fn is_suppressed(target: &ScanTarget, result: &ScanResult) -> bool {
+    let line = match result.line {
+        Some(line) => line,
+        None => return false,
+    };
+
+    [Some(line), line.checked_sub(1)]
+        .into_iter()
+        .flatten()
+        .filter_map(|number| target.line(number))
+        .any(|l| line_suppresses(l, &result.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_suppresses() {
+        assert!(line_suppresses("key = \"abc\" # himitsu:allow", "AwsKey"));
+        assert!(line_suppresses(
+            "// himitsu:allow AwsKey,GenericSecret",
+            "GenericSecret"
+        ));
+        assert!(line_suppresses("/* himitsu:allow AwsKey */", "AwsKey"));
+        assert!(!line_suppresses("// himitsu:allow AwsKey", "GenericSecret"));
+        assert!(!line_suppresses("// himitsu:allowed", "AwsKey"));
+        assert!(!line_suppresses("key = \"abc\"", "AwsKey"));
+    }
+}
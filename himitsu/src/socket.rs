@@ -0,0 +1,60 @@
+use std::{env, path::PathBuf};
+
+/// Environment variable that overrides the default socket path
+pub const SOCKET_ENV: &str = "HIMITSU_SOCKET";
+
+/// Resolve the socket Himitsu listens on, in order of preference from an
+/// explicitly provided path (usually a command line flag), the
+/// `HIMITSU_SOCKET` environment variable and finally the platform default.
+pub fn resolve(path: Option<String>) -> Option<String> {
+    path.filter(|p| !p.is_empty())
+        .or_else(|| env::var(SOCKET_ENV).ok().filter(|p| !p.is_empty()))
+        .or_else(|| default_path().map(|p| p.to_string_lossy().to_string()))
+}
+
+/// The socket inside the group container shared with the macOS app
+#[cfg(target_os = "macos")]
+pub fn default_path() -> Option<PathBuf> {
+    let mut path = home::home_dir()?;
+    path.push("Library/Group Containers/U52857STQU.PassportControl/himitsuSocket");
+    Some(path)
+}
+
+/// `$XDG_RUNTIME_DIR/himitsu/himitsu.sock`, or a per user directory in the
+/// temporary directory when there is no runtime directory, as on some CI
+/// runners. There is no default if neither `XDG_RUNTIME_DIR` nor `USER` is
+/// set.
+#[cfg(not(target_os = "macos"))]
+pub fn default_path() -> Option<PathBuf> {
+    let mut path = match env::var_os("XDG_RUNTIME_DIR").filter(|p| !p.is_empty()) {
+        Some(runtime_dir) => {
+            let mut path = PathBuf::from(runtime_dir);
+            path.push("himitsu");
+            path
+        }
+        None => {
+            // Without a user every user would share the same directory
+            let user = env::var("USER").ok().filter(|user| !user.is_empty())?;
+            let mut path = env::temp_dir();
+            path.push(format!("himitsu-{user}"));
+            path
+        }
+    };
+    path.push("himitsu.sock");
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve(Some("/tmp/h.sock".to_string())).unwrap(),
+            "/tmp/h.sock"
+        );
+        // An empty flag falls through to the environment or the default
+        assert_eq!(resolve(Some(String::new())), resolve(None));
+    }
+}
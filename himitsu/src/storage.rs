@@ -0,0 +1,62 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Himitsu's directory under the XDG base directory named by `variable`,
+/// falling back to `fallback` in the home directory when it isn't set
+pub(crate) fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    let mut path = env::var_os(variable)
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home::home_dir().map(|home| home.join(fallback)))?;
+    path.push("himitsu");
+    Some(path)
+}
+
+/// Replace the file at `path` with `contents`, readable only by us. It is
+/// written to a temporary file first so being interrupted while saving can't
+/// leave a truncated file behind.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)?
+        .write_all(contents)?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = env::temp_dir().join(format!("himitsu-storage-{}", std::process::id()));
+        let path = dir.join("nested/file.json");
+
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}